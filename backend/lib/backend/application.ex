@@ -15,7 +15,6 @@ defmodule Backend.Application do
       worker(BackendWeb.GameState, []),
       worker(BackendWeb.GameLoop, []),
       worker(BackendWeb.GameConf, []),
-      worker(BackendWeb.NativePhysicsServer, []),
     ]
 
     # See https://hexdocs.pm/elixir/Supervisor.html
@@ -9,19 +9,10 @@ defmodule Backend.ProtoMessage do
     ServerChannelMessage,
     ServerMessage,
     ServerError,
-    Snapshot,
-    CreationEvent,
-    PlayerEntity,
-    AsteroidEntity,
-    BarrierEntity,
   }
-  alias NativePhysics
 
-  @entity_types %{
-    player: PlayerEntity,
-    asteroid: AsteroidEntity,
-    barrier: BarrierEntity,
-  }
+  # The field number of `payload` in both `ServerChannelMessage` and `ClientChannelMessage`
+  @channel_message_payload_field 5
 
   def encode_socket_message(%Phoenix.Socket.Message{payload: %{status: :error}} = message) do
     {tick, timestamp} = GameState.get_cur_tick_info
@@ -47,13 +38,76 @@ defmodule Backend.ProtoMessage do
   end
 
   def encode_socket_message(%Phoenix.Socket.Message{} = message) do
-    msg = ServerChannelMessage.new(%{
-      topic: message.topic,
-      event: encode_event(message.event),
-      ref: message.ref,
-      payload: encode_payload(message.payload),
-    })
-    ServerChannelMessage.encode(msg)
+    case encoded_server_message(message.payload) do
+      nil ->
+        msg = ServerChannelMessage.new(%{
+          topic: message.topic,
+          event: encode_event(message.event),
+          ref: message.ref,
+          payload: encode_payload(message.payload),
+        })
+        ServerChannelMessage.encode(msg)
+
+      # `ServerMessage`s encoded by the physics engine are appended as the raw `payload` field of
+      # the channel message rather than being decoded and re-encoded.  Protobuf allows fields to
+      # appear in any order, so this produces the same message as encoding it all at once.
+      server_message ->
+        msg = ServerChannelMessage.new(%{
+          topic: message.topic,
+          event: encode_event(message.event),
+          ref: message.ref,
+        })
+        ServerChannelMessage.encode(msg) <> encode_embedded_field(@channel_message_payload_field, server_message)
+    end
+  end
+
+  defp encoded_server_message(%{response: payload}), do: encoded_server_message(payload)
+  defp encoded_server_message(%{encoded: server_message}), do: server_message
+  defp encoded_server_message(_), do: nil
+
+  # Encodes a length-delimited protobuf field containing the already-encoded bytes of a message
+  defp encode_embedded_field(field_number, bytes) do
+    <<field_number * 8 + 2>> <> encode_varint(byte_size(bytes)) <> bytes
+  end
+
+  defp encode_varint(n) when n < 128, do: <<n>>
+  defp encode_varint(n), do: <<1::1, rem(n, 128)::7>> <> encode_varint(div(n, 128))
+
+  # Splits an encoded `ClientChannelMessage` into the bytes of the channel envelope and the raw
+  # bytes of the `ClientMessage` that it carries so that the latter can be handed off to the
+  # physics engine without being decoded here.  Returns `{envelope, client_message}`.
+  def split_client_channel_message(bytes), do: split_client_channel_message(bytes, <<>>, <<>>)
+
+  defp split_client_channel_message(<<>>, envelope, client_message), do: {envelope, client_message}
+  defp split_client_channel_message(bytes, envelope, client_message) do
+    {key, rest} = decode_varint(bytes)
+    {value, rest} = skip_field_value(rem(key, 8), rest)
+    field = binary_part(bytes, 0, byte_size(bytes) - byte_size(rest))
+
+    if div(key, 8) == @channel_message_payload_field do
+      # Repeated occurrences of an embedded message are merged, so they can simply be joined
+      split_client_channel_message(rest, envelope, client_message <> value)
+    else
+      split_client_channel_message(rest, envelope <> field, client_message)
+    end
+  end
+
+  defp decode_varint(<<0::1, n::7, rest::binary>>), do: {n, rest}
+  defp decode_varint(<<1::1, n::7, rest::binary>>) do
+    {high, rest} = decode_varint(rest)
+    {n + high * 128, rest}
+  end
+
+  # Returns `{value, rest}` for the field value at the start of `bytes`.  Only the varint and
+  # length-delimited wire types are used by the channel messages.
+  defp skip_field_value(0, bytes) do
+    {_, rest} = decode_varint(bytes)
+    {binary_part(bytes, 0, byte_size(bytes) - byte_size(rest)), rest}
+  end
+  defp skip_field_value(2, bytes) do
+    {len, rest} = decode_varint(bytes)
+    <<value::binary-size(len), rest::binary>> = rest
+    {value, rest}
   end
 
   # Converts a UUID into two unsigned 64-bit integers representing its raw byte data
@@ -72,43 +126,6 @@ defmodule Backend.ProtoMessage do
     UUID.uuid4() |> to_proto_uuid
   end
 
-  # Merge the physics state from the backend with the state held in Elixir
-  def encode_game_state_to_snapshot(%{} = game_state) do
-    items = game_state
-      |> Map.merge(NativePhysics.get_snapshot(), fn _, a, b -> Map.merge(a, Map.from_struct(b)) end)
-      |> Map.to_list()
-      |> Enum.map(&to_snapshot_item/1)
-    Snapshot.new(%{items: items})
-  end
-
-  # TODO: optimize this iteration
-  defp to_snapshot_item({player_id, data = %{}}) do
-    %{
-      id: entity_id,
-      center_of_mass_x: center_of_mass_x,
-      center_of_mass_y: center_of_mass_y,
-      movement: movement,
-      entity_type: entity_type,
-      entity_meta: entity_meta,
-    } = data
-    Snapshot.SnapshotItem.new(%{
-      id: to_proto_uuid(entity_id),
-      item: CreationEvent.new(%{
-        center_of_mass_x: center_of_mass_x,
-        center_of_mass_y: center_of_mass_y,
-        movement: movement |> Map.from_struct |> Backend.ProtoMessage.MovementUpdate.new,
-        entity: encode_entity(entity_type, entity_meta),
-      }),
-    })
-  end
-
-  defp to_snapshot_item(other), do: IO.inspect(["`to_snapshot_item` match error", other])
-
-  defp encode_entity(entity_type, entity_meta) do
-    # Convert map keys from strings to atoms
-    {entity_type, @entity_types[entity_type].new(entity_meta)}
-  end
-
   defp encode_event("phx_" <> event) do
     phx_event = PhoenixEvent.value(event |> String.capitalize |> String.to_atom)
     Event.new(%{payload: {:phoenix_event, phx_event} })
@@ -1,13 +1,10 @@
 defmodule BackendWeb.GameChannel do
   use Phoenix.Channel
-  alias BackendWeb.{GameState, GameLoop, GameConf}
+  alias BackendWeb.{GameState, GameConf}
 
   alias Backend.ProtoMessage
   alias Backend.ProtoMessage.{
     ServerMessage,
-    ClientMessage,
-    ConnectMessage,
-    BeamAim,
     StatusUpdate,
     CreationEvent,
     PlayerEntity,
@@ -19,26 +16,18 @@ defmodule BackendWeb.GameChannel do
     send(self(), :after_join)
     uuid = UUID.uuid4()
 
-    # Send a message indicating that the connection was a success and giving the player their UUID
+    # Send a message indicating that the connection was a success and giving the player their UUID.
+    # The room and the snapshot of it are only set up once the player is tracked in `:after_join`.
     proto_uuid = ProtoMessage.to_proto_uuid(uuid)
     connect_success_payload = ServerMessage.Payload.new(%{
       id: proto_uuid,
       payload: {:connect_successful, proto_uuid},
     })
-
-    # Send a snapshot of the current game state to the user
-    snapshot = GameState.get_topic(socket.topic) |> ProtoMessage.encode_game_state_to_snapshot
-    snapshot_payload = ServerMessage.Payload.new(%{
-      id: ProtoMessage.Uuid.new(%{
-        data_1: 0,
-        data_2: 0,
-      }),
-      payload: {:snapshot, snapshot},
-    })
+    connect_success = ServerMessage.encode(ServerMessage.new(%{payload: [connect_success_payload]}))
 
     {
       :ok,
-      [connect_success_payload, snapshot_payload],
+      %{encoded: connect_success},
       assign(socket, :player_id, uuid)
     }
   end
@@ -46,9 +35,10 @@ defmodule BackendWeb.GameChannel do
   def terminate(_reason, socket) do
     player_id = socket.assigns[:player_id]
     # Delete the entitity from the physics world
-    NativePhysics.despawn_user player_id
+    NativePhysics.despawn_user socket.topic, player_id
 
-    # Delete entity from the Elixir-side game state
+    # Delete entity from the Elixir-side game state, tearing down the room if they were the last
+    # player in it
     GameState.untrack_player(socket.topic, socket.assigns.player_id)
 
     # Transmit deletion event for the entity
@@ -69,9 +59,14 @@ defmodule BackendWeb.GameChannel do
 
   def handle_info(:after_join, socket) do
     :ok = GameState.track_player(socket.topic, socket.assigns.player_id, %{})
-
-    # Spawn the user into the Physics Engine world and generate a `MovementUpdate` for them
-    {com_x, com_y, movement_update} = NativePhysics.spawn_user(socket.assigns.player_id)
+    :ok = BackendWeb.Endpoint.subscribe(player_topic(socket.topic, socket.assigns.player_id))
+
+    # Spawn the user into the Physics Engine world and generate a `MovementUpdate` for them along
+    # with a snapshot of the entities around them.  The room exists for as long as the player is
+    # tracked.
+    {tick, _timestamp} = GameState.get_cur_tick_info
+    {com_x, com_y, movement_update, snapshot} =
+      NativePhysics.spawn_user(socket.topic, socket.assigns.player_id, tick)
     internal_movement_update = movement_update
       |> Map.from_struct
       |> Backend.ProtoMessage.MovementUpdate.new
@@ -91,7 +86,8 @@ defmodule BackendWeb.GameChannel do
               entity: {
                 :player,
                 PlayerEntity.new(%{
-                  size: GameConf.get_config("game", "defaultPlayerSize") |> Kernel.trunc
+                  size: GameConf.get_config("game", "defaultPlayerSize") |> Kernel.trunc,
+                  health: GameConf.get_config("game", "playerMaxHealth"),
                 }),
               },
             }),
@@ -100,12 +96,28 @@ defmodule BackendWeb.GameChannel do
       },
     })
 
+    # The snapshot is already encoded by the physics engine
+    push socket, "tick", %{encoded: snapshot}
     broadcast! socket, "game", %{response: [creation_msg_payload]}
     {:noreply, socket}
   end
 
-  def handle_in("game", %ClientMessage{payload: payload}, socket) do
-    handle_payload("game", payload, socket)
+  # Forwards the tick updates for this player that the game loop broadcasts to their own topic
+  def handle_info(%Phoenix.Socket.Broadcast{event: event, payload: payload}, socket) do
+    push socket, event, payload
+    {:noreply, socket}
+  end
+
+  # The topic that updates meant only for the given player in a room are broadcast to
+  def player_topic(room_topic, player_id), do: room_topic <> ":" <> player_id
+
+  # Player inputs are handed to the physics engine still encoded; it decodes them and applies them
+  # during the next tick.
+  def handle_in("game", %{client_message: client_message}, socket) do
+    case NativePhysics.queue_input(socket.topic, socket.assigns.player_id, client_message) do
+      :ok -> nil
+      {:error, reason} -> IO.inspect ["Invalid message from player", socket.assigns.player_id, reason]
+    end
     {:noreply, socket}
   end
 
@@ -116,21 +128,4 @@ defmodule BackendWeb.GameChannel do
   def handle_out(other, payload, socket) do
     IO.puts(["HANDLING OTHER EVT OUT: ", other, payload, socket])
   end
-
-  defp handle_payload("game", {:connect, %ConnectMessage{username: username}}, socket) do
-    queue_user_input(socket, :username, username)
-  end
-  defp handle_payload("game", {:player_move, direction}, socket) do
-    queue_user_input(socket, :direction, direction)
-  end
-  defp handle_payload("game", {:beam_toggle, toggle}, socket) do
-    queue_user_input(socket, :beam_toggle, toggle)
-  end
-  defp handle_payload("game", {:beam_rotation, %BeamAim{} = aim}, socket) do
-    queue_user_input(socket, :beam_rotation, aim)
-  end
-
-  defp queue_user_input(socket, key, value) do
-    GameLoop.queue_message(socket.topic, {socket.assigns.player_id, key, value})
-  end
 end
@@ -2,139 +2,91 @@ defmodule BackendWeb.GameLoop do
   use GenServer
   alias BackendWeb.GameState
   alias BackendWeb.GameConf
-  alias BackendWeb.NativePhysicsServer
+  alias BackendWeb.GameChannel
   alias NativePhysics
-  alias Backend.ProtoMessage
-  alias Backend.ProtoMessage.{ServerMessage, Point2}
 
   @ticks_per_second 60
   @microseconds_per_second 1_000_000
 
   def init(_) do
     start_tick()
-    {:ok, %{}}
+    {:ok, %{pending_topics: MapSet.new()}}
   end
 
   def start_link() do
     GenServer.start_link(__MODULE__, nil, name: __MODULE__)
   end
 
-  def queue_message(topic, message = {_player_id, _key, _value}) do
-    GenServer.call(__MODULE__, {:handle_message, topic, message})
-  end
-
   def handle_info(:tick, state) do
     {:noreply, run_tick(state)}
   end
 
-  def handle_call({:handle_message, topic, new_message}, _from, messages) do
-    diff = NativePhysics.UserDiff.new(new_message)
-    { :reply, nil, Map.put(messages, topic, [diff | Map.get(messages, topic, [])]) }
-  end
-
-  defp run_tick(messages) do
-    {cur_tick, prev_tick_time} = GameState.get_cur_tick_info
+  # Waits out the rest of the frame once, and then ticks every room at the same time.
+  def handle_info({:dispatch, tick}, state) do
     topics = GameState.list_topics()
     if Enum.empty?(topics) do
       Process.send_after(self(), :tick, 16)
+      {:noreply, state}
     else
-      update_topics(topics, cur_tick, prev_tick_time, messages)
+      snapshot_tick_interval = GameConf.get_config "network", "snapshotTickInterval"
+      send_snapshot = rem(tick, snapshot_tick_interval) == 0
+      Enum.each(topics, fn topic -> spawn fn -> tick_topic(topic, send_snapshot, tick) end end)
+      {:noreply, %{state | pending_topics: MapSet.new(topics)}}
     end
-
-    %{}
   end
 
-  defp update_topics([], _tick, _prev_tick_time, _messages), do: nil
-  defp update_topics([topic | rest], tick, prev_tick_time, messages) do
-    topic_state = GameState.get_topic(topic)
-    messages_for_topic = Map.get(messages, topic, [])
-    update_topic(topic, topic_state, tick, prev_tick_time, messages_for_topic)
-    GameState.set_topic(topic, topic_state)
+  # The next tick is only started once every room that was ticked has reported back, so that the
+  # shared tick counter advances exactly once per frame no matter how many rooms there are.
+  def handle_cast({:topic_done, topic}, %{pending_topics: pending} = state) do
+    pending = MapSet.delete(pending, topic)
+    if MapSet.size(pending) == 0 do
+      GameState.incr_tick
+      start_tick()
+    end
 
-    update_topics(rest, tick, prev_tick_time, messages)
+    {:noreply, %{state | pending_topics: pending}}
   end
 
-  defp update_topic(topic, _topic_state, tick, prev_tick_time, player_inputs) do
-    snapshot_tick_interval = GameConf.get_config "network", "snapshotTickInterval"
-    send_snapshot = rem(tick, snapshot_tick_interval) == 0
+  defp run_tick(state) do
+    {cur_tick, prev_tick_time} = GameState.get_cur_tick_info
     time_diff_us = (System.system_time / 1000.0) - (prev_tick_time / 1000.0)
     desired_delay_us = @microseconds_per_second / @ticks_per_second
     delay_us = desired_delay_us - time_diff_us - 400.0
-    delay_us = if delay_us < 0 do
+    delay_ms = if delay_us < 0 do
       0
     else
-      Kernel.trunc delay_us
+      Kernel.trunc(delay_us / 1000)
     end
 
-    spawn NativePhysicsServer, :tick, [Enum.reverse(player_inputs), send_snapshot, delay_us, topic]
+    Process.send_after(self(), {:dispatch, cur_tick}, delay_ms)
+    state
   end
 
-  @spec handle_updates([UserDiff], String.t()) :: nil
-  def handle_updates(updates, topic) do
-    if is_list updates do
-      payload = updates
-        |> Enum.map(&handle_update/1)
-        |> Enum.filter(& !is_nil(&1))
-
-      if !Enum.empty? payload do
-        BackendWeb.Endpoint.broadcast! topic, "tick", %{response: payload}
-      end
-    else
-      IO.inspect ["PHYSICS ENGINE ERROR", updates]
+  # Each room is ticked in its own process so that rooms don't wait on each other.  The loop is
+  # always told that the room is done, even if its tick failed, so that one broken room can't stall
+  # the others.
+  defp tick_topic(topic, send_snapshot, tick) do
+    try do
+      NativePhysics.tick(topic, send_snapshot, tick)
+      |> handle_updates(topic)
+    after
+      GenServer.cast(__MODULE__, {:topic_done, topic})
     end
-
-    # TODO: This is only valid if we have a single topic.  If we have multiple topics, we will
-    # need to emulate a `Promise.all` and keep track of how many of these have finished before
-    # starting the next tick.  So damned annoying...
-    {next_tick, cur_time} = GameState.incr_tick
-    start_tick()
   end
 
-  defp construct_payload(id, inner_payload) do
-    ServerMessage.Payload.new(%{
-      id: ProtoMessage.to_proto_uuid(id),
-      payload: inner_payload,
-    })
-  end
-
-  defp handle_update(%NativePhysics.Update{
-    id: id,
-    update_type: :isometry,
-    payload: payload,
-  }) do
-    internal_movement_update = payload
-      |> Map.from_struct
-      |> Backend.ProtoMessage.MovementUpdate.new
-
-    construct_payload id, { :movement_update, internal_movement_update }
-  end
-
-  defp handle_update(%NativePhysics.Update{
-    id: id,
-    update_type: :player_movement,
-    payload: payload,
-  }) do
-    construct_payload id, { :player_input, payload }
-  end
-
-  defp handle_update(%NativePhysics.Update{
-    id: id,
-    update_type: :beam_toggle,
-    payload: payload,
-  }) do
-    construct_payload id, { :beam_toggle, payload }
-  end
-
-  defp handle_update(%NativePhysics.Update{
-    id: id,
-    update_type: :beam_aim,
-    payload: payload,
-  }) do
-    construct_payload id, { :beam_aim, Point2.new(payload) }
-  end
+  # Each player is sent only the updates for the entities around them, so the messages are
+  # broadcast to the players' own topics rather than to the topic of the whole room.  They are
+  # already encoded by the physics engine and are passed through to the socket untouched.
+  @spec handle_updates([{String.t(), binary()}], String.t()) :: nil
+  def handle_updates(messages, topic) do
+    if is_list messages do
+      Enum.each(messages, fn {player_id, server_message} ->
+        BackendWeb.Endpoint.broadcast! GameChannel.player_topic(topic, player_id), "tick", %{encoded: server_message}
+      end)
+    else
+      IO.inspect ["PHYSICS ENGINE ERROR", messages]
+    end
 
-  defp handle_update(unmatched) do
-    IO.inspect(["~~~~!!!! UNMATCHED UPDATE", unmatched])
     nil
   end
 
@@ -1,5 +1,7 @@
 defmodule BackendWeb.GameState do
   use GenServer
+  alias BackendWeb.GameConf
+  alias NativePhysics
 
   @spec init(any()) :: {:ok, {%{}, 0, any()}}
   def init(_) do
@@ -15,6 +17,9 @@ defmodule BackendWeb.GameState do
     |> Map.get(player_id)
   end
 
+  # Rooms' physics worlds are created and destroyed here as their first player joins and their last
+  # player leaves.  Doing it inside of this process serializes it with tracking players, so a room
+  # can't be torn down while somebody is joining it.
   def track_player(topic, player_id, initial_state) do
     GenServer.call(__MODULE__, {:track_player, topic, player_id, initial_state})
   end
@@ -54,15 +59,31 @@ defmodule BackendWeb.GameState do
   end
 
   def handle_call({:track_player, topic, player_id, initial_state}, _from, {topics, tick, timestamp}) do
+    # Make sure that a physics world exists for this room
+    unless Map.has_key?(topics, topic) do
+      seed = world_seed()
+      if NativePhysics.create_room(topic, seed) do
+        IO.puts(["Created room ", topic, " with world seed ", Integer.to_string(seed)])
+      end
+    end
+
     new_topics = deep_merge(topics, %{topic => %{player_id => initial_state}})
     {:reply, :ok, {new_topics, tick, timestamp}}
   end
 
   def handle_call({:untrack_player, topic, player_id}, _from, {topics, tick, timestamp}) do
-    # Remove the player from the topic map
-    new_topic = Map.delete(Map.get(topics, topic), player_id)
-    # Replace the topic map with the one that has the player removed from it
-    new_topics = Map.replace!(topics, topic, new_topic)
+    # Players whose sockets closed before they finished joining were never tracked
+    new_topic = Map.delete(Map.get(topics, topic, %{}), player_id)
+
+    # Tear down the room's physics world once the last player has left it
+    new_topics = if Enum.empty?(new_topic) do
+      if Map.has_key?(topics, topic) do
+        NativePhysics.destroy_room(topic)
+      end
+      Map.delete(topics, topic)
+    else
+      Map.put(topics, topic, new_topic)
+    end
     {:reply, :ok, {new_topics, tick, timestamp}}
   end
 
@@ -100,4 +121,13 @@ defmodule BackendWeb.GameState do
   defp merge_inner(_key, _left, right), do: right
 
   defp get_time(), do: System.system_time
+
+  # Uses the seed from the `worldSeed` key of the game config if one is set so that worlds can be
+  # reproduced, otherwise picking a random one.
+  defp world_seed() do
+    case GameConf.get_config("game", "worldSeed") do
+      nil -> :rand.uniform(0xFFFFFFFFFFFFFFFF) - 1
+      seed -> seed
+    end
+  end
 end
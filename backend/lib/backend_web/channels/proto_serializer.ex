@@ -25,13 +25,15 @@ defmodule BackendWeb.ProtoSerializer do
     {:socket_push, :binary, Backend.ProtoMessage.encode_socket_message(msg)}
   end
 
+  # The `ClientMessage` payload is passed through still encoded; it's decoded by the physics engine.
   def decode!(message, _opts) do
-    decoded = Backend.ProtoMessage.ClientChannelMessage.decode(message)
+    {envelope, client_message} = Backend.ProtoMessage.split_client_channel_message(message)
+    decoded = Backend.ProtoMessage.ClientChannelMessage.decode(envelope)
 
     %Phoenix.Socket.Message{
       topic: decoded.topic,
       event: decode_event(decoded.event),
-      payload: decoded.payload,
+      payload: %{client_message: client_message},
       ref: decoded.ref,
     }
   end
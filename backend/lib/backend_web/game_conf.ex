@@ -1,7 +1,7 @@
 defmodule BackendWeb.GameConf do
   use Agent
 
-  @config_files ["game", "network", "physics"]
+  @config_files ["game", "network", "physics", "entities"]
   @config_dir "../config"
 
   def start_link() do
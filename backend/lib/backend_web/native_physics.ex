@@ -1,32 +1,46 @@
 defmodule NativePhysics do
   use Rustler, otp_app: :backend, crate: :native_physics
 
-  @spec spawn_user(String.t()) :: {number(), number(), MovementUpdate}
-  def spawn_user(_), do: :erlang.nif_error(:nif_not_loaded)
-
-  @spec despawn_user(String.t()) :: {}
-  def despawn_user(_), do: :erlang.nif_error(:nif_not_loaded)
-
-  @spec tick(any(), boolean(), number()) :: any()
+  @spec create_room(String.t(), non_neg_integer()) :: boolean()
+  def create_room(_, _), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec destroy_room(String.t()) :: boolean()
+  def destroy_room(_), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Saves every entity in the room's world to the file at the given path
+  @spec save_room(String.t(), String.t()) :: :ok | {:error, String.t()}
+  def save_room(_, _), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Creates a room from a world saved with `save_room`.  Returns `false` if the room already exists.
+  @spec load_room(String.t(), String.t()) :: boolean() | {:error, String.t()}
+  def load_room(_, _), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Spawns a player into the room, returning their center of mass, their initial movement, and an
+  # encoded `ServerMessage` containing a snapshot of the entities around them
+  @spec spawn_user(String.t(), String.t(), non_neg_integer()) ::
+    {number(), number(), MovementUpdate, binary()}
+  def spawn_user(_, _, _), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec despawn_user(String.t(), String.t()) :: {}
+  def despawn_user(_, _), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Turns gravitation between heavy objects on or off for the room
+  @spec set_gravitation(String.t(), boolean()) :: :ok
+  def set_gravitation(_, _), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Decodes an encoded `ClientMessage` sent by a player and queues it to be applied during the
+  # next tick
+  @spec queue_input(String.t(), String.t(), binary()) ::
+    :ok | {:error, {:malformed_message, String.t()} | :missing_payload}
+  def queue_input(_, _, _), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Applies all queued inputs and steps the world, returning a list of `{player_id, server_message}`
+  # tuples where each `server_message` is an encoded `ServerMessage` containing the updates for
+  # that player
+  @spec tick(String.t(), boolean(), non_neg_integer()) :: [{String.t(), binary()}]
   def tick(_, _, _), do: :erlang.nif_error(:nif_not_loaded)
 
-  @spec get_snapshot() :: map()
-  def get_snapshot(), do: :erlang.nif_error(:nif_not_loaded)
-
-  defmodule UserDiff do
-    defstruct id: UUID.uuid4(), action_type: :noop, payload: {}
-
-    @spec new({UUID.uuid4, atom(), any()}) :: %NativePhysics.UserDiff{}
-    def new({id, action_type, payload}) do
-      %UserDiff{id: id, action_type: action_type, payload: payload}
-    end
-  end
-
   defmodule MovementUpdate do
     defstruct pos_x: 0.0, pos_y: 0.0, rotation: 0.0, velocity_x: 0.0, velocity_y: 0.0, angular_velocity: 0.0
   end
-
-  defmodule Update do
-    defstruct id: nil, payload: nil, update_type: nil
-  end
 end
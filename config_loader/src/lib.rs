@@ -81,6 +81,7 @@ pub fn build_config() {
     let physics_conf = parse_config_file(include_str!("../../config/physics.json"));
     let network_conf = parse_config_file(include_str!("../../config/network.json"));
     let game_conf = parse_config_file(include_str!("../../config/game.json"));
+    let entities_conf = parse_config_file(include_str!("../../config/entities.json"));
 
     match fs::create_dir(out_dir) {
         Ok(_) => (),
@@ -107,6 +108,7 @@ pub fn build_config() {
         parse_map(physics_conf, "physics"),
         parse_map(network_conf, "network"),
         parse_map(game_conf, "game"),
+        parse_map(entities_conf, "entities"),
     ];
     let template_data: HashMap<String, Value> =
         all_configs.drain(..).fold(HashMap::new(), merge_hashmap);
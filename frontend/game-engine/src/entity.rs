@@ -1,21 +1,23 @@
 use std::hint::unreachable_unchecked;
 
 use nalgebra::{Isometry2, Point2, Vector2};
+use native_physics::physics::beams::BeamHit;
 pub use native_physics::physics::entities::{
-    AsteroidEntity, BarrierEntity, Entity, EntityHandles, EntitySpawn, PlayerEntity,
+    archetype, Entity, EntityHandles, EntitySpawn, ObjectEntity, ObjectShape, PlayerEntity,
 };
-use ncollide2d::query::Ray;
 use nphysics2d::algebra::Velocity2;
-use nphysics2d::object::BodyStatus;
 use uuid::Uuid;
 
+use conf::CONF;
 use game::effects::DrillingParticles;
 use game_state::{get_effects_manager, get_state};
-use physics_math::ray_collision;
 use proto_utils::ServerMessageContent;
-use protos::server_messages::{CreationEvent, CreationEvent_oneof_entity as ProtoEntity};
-use render_methods::{fill_poly, render_line};
-use util::{error, warn, Color};
+use protos::server_messages::{
+    CreationEvent, CreationEvent_oneof_entity as ProtoEntity,
+    ObjectEntity_oneof_shape as ProtoShape,
+};
+use render_methods::{fill_circle, fill_poly, render_line};
+use util::{error, log, warn, Color};
 
 /// An optional piece of client-local state attached to an entity for things such as visual
 /// appearance and transitive state not transmitted authoritatively by the server.
@@ -23,21 +25,38 @@ use util::{error, warn, Color};
 pub enum ClientState {
     Player {
         color: Color,
-        vertices: Vec<Point2<f32>>,
+        /// The last hit of this player's beam reported by the server
+        beam_hit: Option<BeamHit>,
     },
-    Asteroid {
+    Object {
         color: Color,
     },
-    Empty,
 }
 
 pub fn apply_update(
     entity_id: Uuid,
     entity: &mut Entity,
-    _client_state: &mut ClientState,
+    client_state: &mut ClientState,
     update: &ServerMessageContent,
 ) {
     match update {
+        ServerMessageContent::beam_hit(proto_hit) => match client_state {
+            ClientState::Player { beam_hit, .. } => {
+                let point = proto_hit.get_point();
+                *beam_hit = Some(BeamHit {
+                    target_id: if proto_hit.has_target_id() {
+                        Some(proto_hit.get_target_id().clone().into())
+                    } else {
+                        None
+                    },
+                    point: Point2::new(point.x, point.y),
+                });
+            }
+            _ => error(format!(
+                "Received beam hit for non-player entity with id {}!",
+                entity_id
+            )),
+        },
         ServerMessageContent::beam_toggle(_)
         | ServerMessageContent::beam_aim(_)
         | ServerMessageContent::player_input(_) => {
@@ -60,6 +79,10 @@ pub fn apply_update(
             match update {
                 ServerMessageContent::beam_toggle(beam_on) => {
                     player.beam_on = *beam_on;
+                    // The server sends a new hit once the beam is turned back on
+                    if let ClientState::Player { beam_hit, .. } = client_state {
+                        *beam_hit = None;
+                    }
                 }
                 ServerMessageContent::beam_aim(beam_aim) => {
                     player.beam_aim = Point2::new(beam_aim.x, beam_aim.y);
@@ -70,6 +93,19 @@ pub fn apply_update(
                 _ => unreachable!(),
             }
         }
+        ServerMessageContent::health_update(health) => match entity {
+            Entity::Player(player) => player.health = *health,
+            _ => error(format!(
+                "Received health update for non-player entity with id {}!",
+                entity_id
+            )),
+        },
+        ServerMessageContent::player_death(_) => {
+            // The player's entity is deleted by a separate update
+            if entity_id == get_state().player_uuid {
+                log("You were killed!");
+            }
+        }
         _ => warn("Unhandled Update"),
     }
 }
@@ -107,33 +143,107 @@ fn unmatched_state(entity: &Entity, client_state: &ClientState) -> ! {
     }
 }
 
-pub fn render(entity: &Entity, client_state: &ClientState, pos: &Isometry2<f32>, cur_tick: u32) {
+pub fn render(
+    entity_id: Uuid,
+    entity: &Entity,
+    client_state: &ClientState,
+    pos: &Isometry2<f32>,
+    cur_tick: u32,
+) {
     match (entity, client_state) {
-        (Entity::Asteroid(AsteroidEntity { vertices }), ClientState::Asteroid { color }) => {
+        (Entity::Object(ObjectEntity { shape, .. }), ClientState::Object { color }) => {
+            render_object(shape, pos, color)
+        }
+        (Entity::Player(ref player), ClientState::Player { color, beam_hit }) => {
+            render_player(entity_id, player, beam_hit, &pos, color, cur_tick)
+        }
+        _ => unmatched_state(entity, client_state),
+    }
+}
+
+fn render_object(shape: &ObjectShape, pos: &Isometry2<f32>, color: &Color) {
+    match shape {
+        ObjectShape::Polygon(vertices) => {
             let transformed = transform_points(&vertices, pos);
             fill_poly(color, &transformed);
         }
-        (Entity::Player(ref player), ClientState::Player { color, .. }) => {
-            render_player(player, &pos, color, cur_tick)
+        ObjectShape::Circle { radius } => {
+            fill_circle(color, Point2::from_coordinates(pos.translation.vector), *radius)
         }
-        (Entity::Barrier(BarrierEntity { vertices }), ClientState::Empty) => {
-            let transformed = transform_points(&vertices, pos);
-            fill_poly(&Color::new(0, 0, 0), &transformed);
+        ObjectShape::Capsule {
+            half_height,
+            radius,
+        } => {
+            let (half_height, radius) = (*half_height, *radius);
+            let sides = [
+                Point2::new(radius, -half_height),
+                Point2::new(radius, half_height),
+                Point2::new(-radius, half_height),
+                Point2::new(-radius, -half_height),
+            ];
+            fill_poly(color, &transform_points(&sides, pos));
+            fill_circle(color, pos * Point2::new(0., half_height), radius);
+            fill_circle(color, pos * Point2::new(0., -half_height), radius);
         }
-        _ => unmatched_state(entity, client_state),
     }
 }
 
-fn render_player(player: &PlayerEntity, pos: &Isometry2<f32>, color: &Color, cur_tick: u32) {
+/// Returns what the beam of the player with the given ID is hitting along with the point that it
+/// was cast from.  Our own beam is always cast locally so that it follows the cursor without lag;
+/// other players' beams use the hits sent by the server, falling back to casting them locally until
+/// the first one arrives.
+fn get_beam_hit(player_id: Uuid, server_hit: &Option<BeamHit>) -> Option<(BeamHit, Point2<f32>)> {
+    let world = &get_state().world;
+    let body_handle = world.uuid_map.get(&player_id)?.body_handle;
+    let body_pos = world.world.rigid_body(body_handle)?.position();
+    let origin = Point2::from_coordinates(body_pos.translation.vector);
+
+    let hit = match server_hit {
+        Some(hit) if player_id != get_state().player_uuid => hit.clone(),
+        _ => world.cast_beam(&player_id)?,
+    };
+    Some((hit, origin))
+}
+
+fn render_player(
+    player_id: Uuid,
+    player: &PlayerEntity,
+    server_beam_hit: &Option<BeamHit>,
+    pos: &Isometry2<f32>,
+    color: &Color,
+    cur_tick: u32,
+) {
     let PlayerEntity {
         size,
         beam_aim,
         beam_on,
+        health,
         movement: _,
     } = player;
     let transformed = transform_points(&player_verts(*size as f32), pos);
     fill_poly(color, &transformed);
 
+    // Show a health bar above the player once they've taken damage
+    if *health < CONF.game.player_max_health {
+        let half_size = *size as f32 / 2.;
+        let health_width = *size as f32 * (*health / CONF.game.player_max_health);
+        let (x, y) = (
+            pos.translation.vector.x - half_size,
+            pos.translation.vector.y - half_size - 6.,
+        );
+        let health_bar = [
+            x,
+            y,
+            x + health_width,
+            y,
+            x + health_width,
+            y + 3.,
+            x,
+            y + 3.,
+        ];
+        fill_poly(&Color::new(200, 30, 30), &health_bar);
+    }
+
     let beam_gun_len: f32 = 25.;
     let beam_rotation = (Vector2::new(beam_aim.x, beam_aim.y) - pos.translation.vector).normalize();
     let beam_vec = beam_rotation * beam_gun_len;
@@ -148,59 +258,16 @@ fn render_player(player: &PlayerEntity, pos: &Isometry2<f32>, color: &Color, cur
         return;
     }
 
-    let beam_start = beam_gun_endpoint;
-    let beam_endpoint = beam_start + (beam_vec * 10.);
-
-    let ray = Ray::new(beam_gun_endpoint, beam_rotation);
-    let mut possible_collisions = Vec::new();
-    get_state()
-        .world
-        .world
-        .collision_world()
-        .broad_phase()
-        .interferences_with_ray(&ray, &mut possible_collisions);
-    let broad_phase_miss = possible_collisions.is_empty();
-
-    let collision_check_opt = possible_collisions
-        .into_iter()
-        .map(|collider_handle| -> Option<(Point2<f32>, f32)> {
-            let uuid = get_state().world.handle_map.get(collider_handle).unwrap();
-            let EntityHandles {
-                entity,
-                data: client_state,
-                ..
-            } = get_state()
-                .world
-                .uuid_map
-                .get(uuid)
-                .expect("UUID in `handle_map` but not `uuid_map`");
-            let target_pos = get_state()
-                .world
-                .world
-                .collider(*collider_handle)
-                .unwrap()
-                .position();
-
-            let verts = get_vertices(entity, client_state);
-            ray_collision(beam_start, beam_rotation, verts, target_pos)
-        }).fold(None, |acc, distance_opt| -> Option<(Point2<f32>, f32)> {
-            match (acc, distance_opt) {
-                (None, Some(res)) => Some(res),
-                (Some((nearest_collision, smallest_distance)), Some((collision, dist))) => {
-                    if smallest_distance < dist {
-                        Some((nearest_collision, smallest_distance))
-                    } else {
-                        Some((collision, dist))
-                    }
-                }
-                (Some(acc), None) => Some(acc),
-                (None, None) => None,
-            }
-        });
+    let (hit, origin) = match get_beam_hit(player_id, server_beam_hit) {
+        Some(hit) => hit,
+        None => return,
+    };
+    // Hits are cast from the player's actual position, which differs from `pos` for ghosts
+    let beam_endpoint = hit.point + (pos.translation.vector - origin.coords);
 
-    let (line_color, beam_endpoint) = if let Some((nearest_collision, _)) = collision_check_opt {
+    let line_color = if hit.target_id.is_some() {
         let drilling_effect = DrillingParticles::new(
-            nearest_collision,
+            beam_endpoint,
             cur_tick,
             5,
             4,
@@ -212,35 +279,16 @@ fn render_player(player: &PlayerEntity, pos: &Isometry2<f32>, color: &Color, cur
             },
         );
         get_effects_manager().add_effect(box drilling_effect);
-        let color = &Color {
+        &Color {
             red: 255,
             green: 0,
             blue: 0,
-        };
-        (color, nearest_collision)
+        }
     } else {
-        let color = if broad_phase_miss {
-            &Color {
-                red: 0,
-                green: 0,
-                blue: 255,
-            }
-        } else {
-            &color
-        };
-        (color, beam_endpoint)
+        color
     };
 
-    render_line(&line_color, 1, beam_start, beam_endpoint);
-}
-
-fn get_vertices<'a>(entity: &'a Entity, client_state: &'a ClientState) -> &'a [Point2<f32>] {
-    match (entity, client_state) {
-        (Entity::Asteroid(AsteroidEntity { vertices }), _)
-        | (Entity::Barrier(BarrierEntity { vertices }), _) => vertices,
-        (Entity::Player(_), ClientState::Player { vertices, .. }) => vertices,
-        _ => unmatched_state(entity, client_state),
-    }
+    render_line(line_color, 1, beam_gun_endpoint, beam_endpoint);
 }
 
 fn map_vertices(verts: &[f32]) -> Vec<Point2<f32>> {
@@ -261,46 +309,62 @@ pub fn parse_proto_entity(creation_evt: &CreationEvent) -> Option<EntitySpawn<Cl
         }
     };
 
-    let mut body_status = BodyStatus::Dynamic;
-
     let (entity, client_state) = match entity {
         ProtoEntity::player(proto_player) => {
             let size = proto_player.get_size();
-            let half_size = (size as f32) / 2.;
-            let entity = Entity::Player(PlayerEntity::new(size));
+            let mut player = PlayerEntity::new(size);
+            player.health = proto_player.get_health();
+            let entity = Entity::Player(player);
             let client_state = ClientState::Player {
                 color: Color::random(),
-                vertices: vec![
-                    Point2::new(half_size, half_size),
-                    Point2::new(-half_size, half_size),
-                    Point2::new(-half_size, -half_size),
-                    Point2::new(half_size, -half_size),
-                ],
+                beam_hit: None,
             };
             (entity, client_state)
         }
-        ProtoEntity::asteroid(asteroid) => {
-            let vertices = map_vertices(&asteroid.get_vert_coords());
-            let entity = Entity::Asteroid(AsteroidEntity { vertices });
-            let client_state = ClientState::Asteroid {
-                color: Color::random(),
+        ProtoEntity::object(proto_object) => {
+            let archetype = match archetype(proto_object.get_archetype()) {
+                Some(archetype) => archetype,
+                None => {
+                    warn(format!(
+                        "Received object with unknown archetype `{}`",
+                        proto_object.get_archetype()
+                    ));
+                    return None;
+                }
             };
-            (entity, client_state)
-        }
-        ProtoEntity::barrier(barrier) => {
-            let vertices = map_vertices(&barrier.get_vert_coords());
-            let entity = Entity::Barrier(BarrierEntity { vertices });
-            let client_state = ClientState::Empty;
-            body_status = BodyStatus::Static;
-            (entity, client_state)
+            let shape = match proto_object.shape.as_ref() {
+                Some(ProtoShape::polygon(polygon)) => {
+                    ObjectShape::Polygon(map_vertices(polygon.get_vert_coords()))
+                }
+                Some(ProtoShape::circle(circle)) => ObjectShape::Circle {
+                    radius: circle.get_radius(),
+                },
+                Some(ProtoShape::capsule(capsule)) => ObjectShape::Capsule {
+                    half_height: capsule.get_half_height(),
+                    radius: capsule.get_radius(),
+                },
+                None => {
+                    error("Received `ObjectEntity` without a `shape` field");
+                    return None;
+                }
+            };
+            let entity = Entity::Object(ObjectEntity::new(archetype, shape));
+            let color = if archetype.random_color {
+                Color::random()
+            } else {
+                let [red, green, blue] = archetype.color;
+                Color::new(red, green, blue)
+            };
+            (entity, ClientState::Object { color })
         }
     };
 
     Some(EntitySpawn {
+        body_status: entity.archetype().body_status(),
         entity,
         isometry: pos,
         velocity,
         data: client_state,
-        body_status,
+        material: None,
     })
 }
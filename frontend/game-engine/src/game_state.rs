@@ -6,8 +6,10 @@ use std::ptr;
 use std::sync::atomic::Ordering;
 use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT};
 
+use nalgebra::{Isometry2, Point2, Translation2};
 use native_physics::physics::entities::EntityHandles;
 use native_physics::physics::world::PhysicsWorldInner as PhysicsWorld;
+use native_physics::physics::wrap::ghost_offsets;
 use uuid::Uuid;
 
 use super::{init_input_handlers, start_game_loop};
@@ -46,6 +48,9 @@ pub fn get_cur_held_keys() -> &'static mut CurHeldKeys {
 pub struct GameState {
     pub initial_tick: u32,
     pub cur_tick: u32,
+    /// The tick of the newest message from the server that has been applied, reported to the
+    /// server with our inputs so that it can evaluate our beam against what we're seeing
+    pub render_tick: u32,
     pub player_uuid: Uuid,
     pub world: PhysicsWorld<ClientState>,
     pub msg_buffer: CircularBuffer<ServerMessage>,
@@ -56,6 +61,7 @@ impl GameState {
         GameState {
             initial_tick: 0,
             cur_tick: 0,
+            render_tick: 0,
             player_uuid: Uuid::nil(), // Placeholder until we are assigned an ID by the server
             world: PhysicsWorld::new(),
             msg_buffer: CircularBuffer::new(CONF.network.message_buffer_size),
@@ -80,6 +86,7 @@ impl GameState {
     fn apply_msg(&mut self, msg: ServerMessage) {
         let tick = msg.get_tick();
         let timestamp = msg.get_timestamp();
+        self.render_tick = tick;
 
         for InnerServerMessage { id, content } in parse_server_msg_payload(msg) {
             self.apply_inner_msg(id, content, tick, timestamp)
@@ -243,7 +250,20 @@ impl GameState {
                     .position(),
             };
 
-            render(entity, client_state, &pos, self.cur_tick);
+            render(*id, entity, client_state, &pos, self.cur_tick);
+
+            // In a wrapping world, entities near an edge are also drawn on the opposite side of
+            // the world so that they appear smoothly as they cross over
+            if CONF.game.wrap_world {
+                let center = Point2::from_coordinates(pos.translation.vector);
+                for offset in ghost_offsets(&center, CONF.game.wrap_ghost_margin) {
+                    let ghost_pos = Isometry2::from_parts(
+                        Translation2::from_vector(pos.translation.vector + offset),
+                        pos.rotation,
+                    );
+                    render(*id, entity, client_state, &ghost_pos, self.cur_tick);
+                }
+            }
         }
 
         self.cur_tick
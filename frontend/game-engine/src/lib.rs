@@ -20,7 +20,6 @@ pub mod entity;
 pub mod game;
 pub mod game_state;
 pub mod phoenix_proto;
-pub mod physics_math;
 pub mod proto_utils;
 pub mod protos;
 pub mod render_effects;
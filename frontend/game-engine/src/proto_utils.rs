@@ -7,6 +7,7 @@ use protobuf::Message;
 use uuid::Uuid;
 
 use conf::CONF;
+use game_state::get_state;
 use phoenix_proto::send_channel_message;
 use protos::channel_messages::Event;
 use protos::client_messages::{ClientMessage, ClientMessage_oneof_payload as ClientMessageContent};
@@ -121,6 +122,7 @@ pub fn msg_to_bytes<M: Message>(msg: M) -> Vec<u8> {
 pub fn send_user_message(payload: ClientMessageContent) {
     let mut client_msg = ClientMessage::new();
     client_msg.payload = Some(payload);
+    client_msg.set_render_tick(get_state().render_tick);
 
     let mut event = Event::new();
     event.set_custom_event(CONF.network.custom_event_name.into());
@@ -1,3 +1,5 @@
+use std::f32::consts::PI;
+
 use nalgebra::Point2;
 
 use util::Color;
@@ -41,6 +43,20 @@ pub fn render_arc(
     )
 }
 
+/// Draws a filled circle by stroking an arc around its center that is as wide as its radius
+#[inline(always)]
+pub fn fill_circle(color: &Color, pos: Point2<f32>, radius: f32) {
+    render_arc(
+        color,
+        pos,
+        radius.round() as u16,
+        (radius / 2.).round() as u16,
+        0.,
+        2. * PI,
+        false,
+    )
+}
+
 #[inline(always)]
 pub fn render_line(color: &Color, width: u16, p1: Point2<f32>, p2: Point2<f32>) {
     super::render_line(
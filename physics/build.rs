@@ -1,5 +1,31 @@
 extern crate config_loader;
+#[cfg(feature = "proto")]
+extern crate protobuf_codegen_pure;
+
+#[cfg(feature = "proto")]
+fn build_protos() {
+    // Generates Rust code from protocol buffer definitions
+    protobuf_codegen_pure::run(protobuf_codegen_pure::Args {
+        out_dir: "src/protos",
+        input: &[
+            "../schema/message_common.proto",
+            "../schema/server_messages.proto",
+            "../schema/client_messages.proto",
+            "../schema/channel_messages.proto",
+        ],
+        includes: &["../schema"],
+        customize: protobuf_codegen_pure::Customize {
+            ..Default::default()
+        },
+    }).expect("Protobuf codegen error");
+
+    println!("cargo:rerun-if-changed=../schema");
+}
 
 fn main() {
+    #[cfg(feature = "proto")]
+    build_protos();
+
     config_loader::build_config();
+    println!("cargo:rerun-if-changed=../config");
 }
@@ -0,0 +1,44 @@
+//! Replays a session recorded with `recordSessions` enabled in the game config, verifying that the
+//! simulation reproduces it exactly.  Build with:
+//!
+//! `cargo run --bin replay --no-default-features -- <recording file>`
+
+extern crate native_physics;
+
+use std::env;
+use std::process;
+
+use native_physics::physics::recording::{replay, ReplayError};
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: replay <recording file>");
+            process::exit(1);
+        }
+    };
+
+    match replay(&path) {
+        Ok(world) => println!(
+            "Replayed {} ticks; the world matched the recording after every one",
+            world.tick_count
+        ),
+        Err(ReplayError::Diverged {
+            tick,
+            expected,
+            actual,
+        }) => {
+            eprintln!(
+                "The replay diverged from the recording after tick {} (expected checksum {:x}, \
+                 got {:x})",
+                tick, expected, actual
+            );
+            process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("Error while replaying recording: {:?}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Runs the game server natively without Elixir.  Build with:
+//!
+//! `cargo run --bin standalone_server --no-default-features --features standalone-server`
+//!
+//! An alternative listen address can be provided as the first argument and a world seed as the
+//! second.  If no seed is provided, a random one is chosen and printed so that the world can be
+//! reproduced later.
+
+extern crate native_physics;
+extern crate rand;
+
+use std::env;
+use std::process;
+
+const DEFAULT_ADDR: &'static str = "127.0.0.1:4000";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let addr = args.next().unwrap_or_else(|| DEFAULT_ADDR.into());
+    let seed: u64 = match args.next() {
+        Some(seed) => seed.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid seed provided: {}", seed);
+            process::exit(1);
+        }),
+        None => rand::random(),
+    };
+
+    if let Err(err) = native_physics::standalone::run(&addr, seed) {
+        eprintln!("Error while running standalone server: {:?}", err);
+        process::exit(1);
+    }
+}
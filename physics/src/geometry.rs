@@ -0,0 +1,381 @@
+//! Polygon utilities used to generate and manipulate entity shapes.  All polygons are represented
+//! as lists of vertices in counter-clockwise order without the first vertex repeated at the end.
+
+use std::cmp::Ordering;
+
+use nalgebra::{Point2, Vector2};
+
+#[inline(always)]
+fn cross(o: &Point2<f32>, a: &Point2<f32>, b: &Point2<f32>) -> f32 {
+    (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+}
+
+/// Computes the convex hull of a set of points using Andrew's monotone chain algorithm.  The
+/// returned hull is in counter-clockwise order and contains no collinear points.
+pub fn convex_hull(points: &[Point2<f32>]) -> Vec<Point2<f32>> {
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| {
+        a.x.partial_cmp(&b.x)
+            .unwrap_or(Ordering::Equal)
+            .then(a.y.partial_cmp(&b.y).unwrap_or(Ordering::Equal))
+    });
+    sorted.dedup();
+
+    if sorted.len() < 3 {
+        return sorted;
+    }
+
+    let mut hull: Vec<Point2<f32>> = Vec::with_capacity(sorted.len() * 2);
+    // Lower hull
+    for pt in &sorted {
+        while hull.len() >= 2 && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], pt) <= 0. {
+            hull.pop();
+        }
+        hull.push(*pt);
+    }
+
+    // Upper hull
+    let lower_len = hull.len() + 1;
+    for pt in sorted.iter().rev().skip(1) {
+        while hull.len() >= lower_len
+            && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], pt) <= 0.
+        {
+            hull.pop();
+        }
+        hull.push(*pt);
+    }
+
+    // The last point is the same as the first one
+    hull.pop();
+    hull
+}
+
+/// Returns the signed area of the polygon, which is positive if its vertices are in
+/// counter-clockwise order.
+pub fn signed_area(vertices: &[Point2<f32>]) -> f32 {
+    let mut area = 0.;
+    for i in 0..vertices.len() {
+        let (a, b) = (vertices[i], vertices[(i + 1) % vertices.len()]);
+        area += a.x * b.y - b.x * a.y;
+    }
+
+    area / 2.
+}
+
+pub fn area(vertices: &[Point2<f32>]) -> f32 {
+    signed_area(vertices).abs()
+}
+
+/// Returns the centroid (center of mass for uniform density) of a simple polygon
+pub fn centroid(vertices: &[Point2<f32>]) -> Point2<f32> {
+    let area = signed_area(vertices);
+    if area == 0. {
+        // Degenerate polygon; just average the vertices
+        let sum = vertices
+            .iter()
+            .fold(Vector2::zeros(), |acc, pt| acc + pt.coords);
+        return Point2::from_coordinates(sum / vertices.len() as f32);
+    }
+
+    let mut sum = Vector2::zeros();
+    for i in 0..vertices.len() {
+        let (a, b) = (vertices[i], vertices[(i + 1) % vertices.len()]);
+        let factor = a.x * b.y - b.x * a.y;
+        sum += (a.coords + b.coords) * factor;
+    }
+
+    Point2::from_coordinates(sum / (6. * area))
+}
+
+/// Splits a convex polygon along the line passing through `point` in the direction `dir`,
+/// returning the pieces to the left and right of the line.  Both pieces are convex and in
+/// counter-clockwise order.  If the line doesn't cross the polygon, one of the pieces will have
+/// fewer than three vertices.
+pub fn split_convex_polygon(
+    vertices: &[Point2<f32>],
+    point: &Point2<f32>,
+    dir: &Vector2<f32>,
+) -> (Vec<Point2<f32>>, Vec<Point2<f32>>) {
+    // Positive for points to the left of the line, negative for points to the right
+    let side = |pt: &Point2<f32>| dir.x * (pt.y - point.y) - dir.y * (pt.x - point.x);
+
+    let (mut left, mut right) = (Vec::new(), Vec::new());
+    for i in 0..vertices.len() {
+        let (a, b) = (vertices[i], vertices[(i + 1) % vertices.len()]);
+        let (side_a, side_b) = (side(&a), side(&b));
+
+        if side_a >= 0. {
+            left.push(a);
+        }
+        if side_a <= 0. {
+            right.push(a);
+        }
+
+        // The edge crosses the line, so both pieces get the intersection point
+        if (side_a > 0. && side_b < 0.) || (side_a < 0. && side_b > 0.) {
+            let t = side_a / (side_a - side_b);
+            let intersection = a + (b - a) * t;
+            left.push(intersection);
+            right.push(intersection);
+        }
+    }
+
+    (left, right)
+}
+
+/// Returns `true` if all of the polygon's corners turn the same way.  Polygons with collinear
+/// vertices are not considered convex.
+pub fn is_convex(vertices: &[Point2<f32>]) -> bool {
+    if vertices.len() < 3 {
+        return false;
+    }
+
+    let orientation = signed_area(vertices).signum();
+    (0..vertices.len()).all(|i| {
+        let (a, b, c) = (
+            &vertices[i],
+            &vertices[(i + 1) % vertices.len()],
+            &vertices[(i + 2) % vertices.len()],
+        );
+        cross(a, b, c) * orientation > 0.
+    })
+}
+
+/// Returns a copy of the polygon in counter-clockwise order with duplicate and collinear vertices
+/// removed
+fn simplify_polygon(vertices: &[Point2<f32>]) -> Vec<Point2<f32>> {
+    let mut simplified = vertices.to_vec();
+    if signed_area(&simplified) < 0. {
+        simplified.reverse();
+    }
+
+    let mut i = 0;
+    while simplified.len() >= 3 && i < simplified.len() {
+        let len = simplified.len();
+        let (prev, next) = (simplified[(i + len - 1) % len], simplified[(i + 1) % len]);
+        if cross(&prev, &simplified[i], &next) == 0. {
+            simplified.remove(i);
+            // The previous vertex may have become collinear with its new neighbors
+            i = i.saturating_sub(1);
+        } else {
+            i += 1;
+        }
+    }
+
+    simplified
+}
+
+#[inline(always)]
+fn in_triangle(pt: &Point2<f32>, a: &Point2<f32>, b: &Point2<f32>, c: &Point2<f32>) -> bool {
+    cross(a, b, pt) >= 0. && cross(b, c, pt) >= 0. && cross(c, a, pt) >= 0.
+}
+
+/// Triangulates a simple counter-clockwise polygon by ear clipping, returning the triangles as
+/// indices into `vertices`.  Polygons that aren't simple may only be partially triangulated.
+fn triangulate(vertices: &[Point2<f32>]) -> Vec<Vec<usize>> {
+    let mut remaining: Vec<usize> = (0..vertices.len()).collect();
+    let mut triangles = Vec::with_capacity(vertices.len() - 2);
+
+    while remaining.len() > 3 {
+        let ear = {
+            let len = remaining.len();
+            let corner = |i: usize| {
+                (remaining[(i + len - 1) % len], remaining[i], remaining[(i + 1) % len])
+            };
+
+            (0..len)
+                .map(|i| (i, corner(i)))
+                .find(|&(_, (prev, cur, next))| {
+                    let (a, b, c) = (&vertices[prev], &vertices[cur], &vertices[next]);
+                    // Convex corners with no other vertices inside of them can be clipped off
+                    cross(a, b, c) > 0.
+                        && !remaining.iter().any(|&j| {
+                            j != prev && j != cur && j != next && in_triangle(&vertices[j], a, b, c)
+                        })
+                })
+        };
+
+        let (ear_ix, (prev, cur, next)) = match ear {
+            Some(ear) => ear,
+            None => break,
+        };
+        triangles.push(vec![prev, cur, next]);
+        remaining.remove(ear_ix);
+    }
+
+    if remaining.len() == 3
+        && cross(
+            &vertices[remaining[0]],
+            &vertices[remaining[1]],
+            &vertices[remaining[2]],
+        ) > 0.
+    {
+        triangles.push(remaining);
+    }
+
+    triangles
+}
+
+/// Merges the two polygons, given as lists of indices into `vertices`, if they share an edge and
+/// the result is convex
+fn merge_convex(vertices: &[Point2<f32>], a: &[usize], b: &[usize]) -> Option<Vec<usize>> {
+    for i in 0..a.len() {
+        let (from, to) = (a[i], a[(i + 1) % a.len()]);
+        // Shared edges run in opposite directions in the two polygons
+        let j = match (0..b.len()).find(|&j| b[j] == to && b[(j + 1) % b.len()] == from) {
+            Some(j) => j,
+            None => continue,
+        };
+
+        // Walk around `a` from `to` back to `from`, then around `b` from `from` back to `to`
+        let mut merged: Vec<usize> = (1..=a.len()).map(|k| a[(i + k) % a.len()]).collect();
+        merged.extend((2..b.len()).map(|k| b[(j + k) % b.len()]));
+
+        let points: Vec<Point2<f32>> = merged.iter().map(|&ix| vertices[ix]).collect();
+        return if is_convex(&points) {
+            Some(merged)
+        } else {
+            None
+        };
+    }
+
+    None
+}
+
+/// Decomposes a simple polygon into convex polygons that exactly cover it.  The polygon is
+/// triangulated and then neighboring pieces are greedily merged as long as they stay convex
+/// (the Hertel-Mehlhorn algorithm), which yields at most four times the optimal number of pieces.
+/// All of the pieces are in counter-clockwise order.  Degenerate polygons yield no pieces.
+pub fn convex_decomposition(vertices: &[Point2<f32>]) -> Vec<Vec<Point2<f32>>> {
+    let vertices = simplify_polygon(vertices);
+    if vertices.len() < 3 {
+        return Vec::new();
+    }
+    if is_convex(&vertices) {
+        return vec![vertices];
+    }
+
+    let mut pieces = triangulate(&vertices);
+    let mut merged_any = true;
+    while merged_any {
+        merged_any = false;
+        'search: for i in 0..pieces.len() {
+            for j in (i + 1)..pieces.len() {
+                if let Some(merged) = merge_convex(&vertices, &pieces[i], &pieces[j]) {
+                    pieces[i] = merged;
+                    pieces.swap_remove(j);
+                    merged_any = true;
+                    break 'search;
+                }
+            }
+        }
+    }
+
+    pieces
+        .into_iter()
+        .map(|piece| piece.into_iter().map(|ix| vertices[ix]).collect())
+        .collect()
+}
+
+#[test]
+fn hull_removes_interior_and_collinear_points() {
+    let points = [
+        Point2::new(0., 0.),
+        Point2::new(1., 0.),
+        Point2::new(2., 0.),
+        Point2::new(2., 2.),
+        Point2::new(1., 1.),
+        Point2::new(0., 2.),
+    ];
+    let hull = convex_hull(&points);
+    assert_eq!(
+        hull,
+        vec![
+            Point2::new(0., 0.),
+            Point2::new(2., 0.),
+            Point2::new(2., 2.),
+            Point2::new(0., 2.),
+        ]
+    );
+    assert!(signed_area(&hull) > 0.);
+}
+
+#[test]
+fn area_and_centroid() {
+    let square = [
+        Point2::new(1., 1.),
+        Point2::new(3., 1.),
+        Point2::new(3., 3.),
+        Point2::new(1., 3.),
+    ];
+    assert_eq!(area(&square), 4.);
+    assert_eq!(centroid(&square), Point2::new(2., 2.));
+
+    let reversed: Vec<_> = square.iter().rev().cloned().collect();
+    assert_eq!(signed_area(&reversed), -4.);
+    assert_eq!(centroid(&reversed), Point2::new(2., 2.));
+}
+
+#[test]
+fn split_square_in_half() {
+    let square = [
+        Point2::new(0., 0.),
+        Point2::new(2., 0.),
+        Point2::new(2., 2.),
+        Point2::new(0., 2.),
+    ];
+    let (left, right) =
+        split_convex_polygon(&square, &Point2::new(1., 1.), &Vector2::new(0., 1.));
+
+    assert_eq!(
+        left,
+        vec![
+            Point2::new(0., 0.),
+            Point2::new(1., 0.),
+            Point2::new(1., 2.),
+            Point2::new(0., 2.),
+        ]
+    );
+    assert_eq!(area(&left), 2.);
+    assert_eq!(area(&right), 2.);
+    assert!(signed_area(&right) > 0.);
+
+    // A line that misses the polygon leaves it whole
+    let (left, right) =
+        split_convex_polygon(&square, &Point2::new(5., 0.), &Vector2::new(0., 1.));
+    assert_eq!(left, square.to_vec());
+    assert!(right.is_empty());
+}
+
+#[test]
+fn concave_polygons_are_decomposed_into_convex_pieces() {
+    // An L shape, given clockwise and with a redundant vertex along its bottom edge
+    let l_shape = [
+        Point2::new(0., 0.),
+        Point2::new(0., 3.),
+        Point2::new(1., 3.),
+        Point2::new(1., 1.),
+        Point2::new(3., 1.),
+        Point2::new(3., 0.),
+        Point2::new(1.5, 0.),
+    ];
+    assert!(!is_convex(&l_shape));
+
+    let pieces = convex_decomposition(&l_shape);
+    assert_eq!(pieces.len(), 2);
+    let total_area: f32 = pieces.iter().map(|piece| area(piece)).sum();
+    assert_eq!(total_area, area(&l_shape));
+    for piece in &pieces {
+        assert!(is_convex(piece));
+        assert!(signed_area(piece) > 0.);
+    }
+
+    let square = [
+        Point2::new(0., 0.),
+        Point2::new(1., 0.),
+        Point2::new(1., 1.),
+        Point2::new(0., 1.),
+    ];
+    assert_eq!(convex_decomposition(&square), vec![square.to_vec()]);
+    assert!(convex_decomposition(&square[..2]).is_empty());
+}
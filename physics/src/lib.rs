@@ -1,11 +1,15 @@
 #![feature(nll)]
 
-#[macro_use]
+#[cfg_attr(feature = "elixir-interop", macro_use)]
 extern crate lazy_static;
 extern crate nalgebra;
 extern crate ncollide2d;
 extern crate nphysics2d;
+#[cfg(feature = "proto")]
+extern crate protobuf;
 extern crate rand;
+#[cfg(feature = "standalone-server")]
+extern crate tungstenite;
 extern crate uuid;
 
 #[cfg(feature = "elixir-interop")]
@@ -17,157 +21,161 @@ extern crate rustler;
 extern crate rustler_codegen;
 
 pub mod conf;
+pub mod geometry;
 pub mod physics;
+#[cfg(feature = "proto")]
+pub mod proto_utils;
+#[cfg(feature = "proto")]
+pub mod protos;
+#[cfg(feature = "standalone-server")]
+pub mod standalone;
 pub mod worldgen;
 
 #[cfg(feature = "elixir-interop")]
 pub mod atoms {
     rustler_atoms! {
-        // Movement Directions
-        atom UP;
-        atom UP_RIGHT;
-        atom RIGHT;
-        atom DOWN_RIGHT;
-        atom DOWN;
-        atom DOWN_LEFT;
-        atom LEFT;
-        atom UP_LEFT;
-        atom STOP;
-
-        // Action Types
-        atom direction;
-        atom beam_rotation;
-        atom beam_toggle;
-
-        // Update Types
-        atom isometry;
-        atom beam_event;
-        atom username;
-        atom player_movement;
-
-        // Entity Types
-        atom player;
-        atom asteroid;
-        atom barrier;
-
-        // Proximity Events
-        atom intersecting;
-        atom disjoint;
-
-        // Map/Struct Keys
-        atom x;
-        atom y;
-        atom size;
-        atom movement;
-        atom beam_aim;
-        atom beam_on;
-        atom vert_coords;
+        atom ok;
+        atom error;
+
+        // Input Errors
+        atom malformed_message;
+        atom missing_payload;
     }
 }
 
 #[cfg(feature = "elixir-interop")]
 pub mod ext {
-    use std::thread;
-    use std::time::Duration;
+    use std::sync::Arc;
 
     use rustler::error::Error as NifError;
     use rustler::schedule::SchedulerFlags;
-    use rustler::types::{atom::Atom, ListIterator};
+    use rustler::types::binary::Binary;
     use rustler::{Encoder, Env, NifResult, Term};
 
     use super::atoms;
-    use super::physics::{
-        server::{InternalUserDiff, InternalUserDiffAction},
-        Movement,
-    };
+    use super::physics::server::{PhysicsWorld, ROOMS};
+    use super::proto_utils::ClientMessageError;
 
     rustler_export_nifs!(
         "Elixir.NativePhysics",
         [
-            ("spawn_user", 1, spawn_user),
-            ("despawn_user", 1, despawn_user),
-            ("tick", 3, tick, SchedulerFlags::DirtyCpu),
-            ("get_snapshot", 0, super::physics::server::get_snapshot)
+            ("create_room", 2, create_room),
+            ("destroy_room", 1, destroy_room),
+            ("save_room", 2, save_room, SchedulerFlags::DirtyIo),
+            ("load_room", 2, load_room, SchedulerFlags::DirtyIo),
+            ("spawn_user", 3, spawn_user),
+            ("despawn_user", 2, despawn_user),
+            ("set_gravitation", 2, set_gravitation),
+            ("queue_input", 3, queue_input),
+            ("tick", 3, tick, SchedulerFlags::DirtyCpu)
         ],
         None
     );
 
-    #[derive(NifStruct)]
-    #[module = "NativePhysics.UserDiff"]
-    pub struct UserDiff<'a> {
-        pub id: String,
-        pub action_type: Atom,
-        pub payload: Term<'a>,
+    /// Looks up the room with the ID contained in the provided term, returning an error atom if
+    /// no such room exists.
+    fn get_room<'a>(room_id: Term<'a>) -> NifResult<Arc<PhysicsWorld>> {
+        let room_id: String = room_id.decode()?;
+        ROOMS.get(&room_id).ok_or(NifError::Atom("no_such_room"))
     }
 
-    impl<'a> UserDiff<'a> {
-        pub fn parse(self, env: Env<'a>) -> NifResult<InternalUserDiff> {
-            let internal_action = match self.action_type {
-                t if atoms::direction() == t => {
-                    let movement = Movement::from_term(self.payload)?;
-                    InternalUserDiffAction::Movement(movement)
-                }
-                t if atoms::beam_rotation() == t => {
-                    let x: u32 = self.payload.map_get(atoms::x().encode(env))?.decode()?;
-                    let y: u32 = self.payload.map_get(atoms::y().encode(env))?.decode()?;
-                    InternalUserDiffAction::BeamAim {
-                        x: x as f32,
-                        y: y as f32,
-                    }
-                }
-                t if atoms::beam_toggle() == t => {
-                    let beam_on: bool = self.payload.decode()?;
-                    InternalUserDiffAction::BeamToggle(beam_on)
-                }
-                t if atoms::username() == t => {
-                    let username: String = self.payload.decode()?;
-                    InternalUserDiffAction::Username(username)
-                }
-                _ => Err(NifError::Atom("invalid_action_type"))?,
-            };
-
-            Ok(InternalUserDiff {
-                id: self.id,
-                action: internal_action,
-            })
-        }
+    /// Decodes an encoded `ClientMessage` sent by a player and queues it to be applied during the
+    /// next tick.  Returns `:ok`, or `{:error, reason}` if the message couldn't be decoded.
+    pub fn queue_input<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+        let room = get_room(args[0])?;
+        let player_id: String = args[1].decode()?;
+        let bytes: Binary = args[2].decode()?;
+
+        let res = match super::physics::server::queue_input(&room, player_id, bytes.as_slice()) {
+            Ok(()) => atoms::ok().encode(env),
+            Err(ClientMessageError::Malformed(err)) => (
+                atoms::error(),
+                (atoms::malformed_message(), err.to_string()),
+            )
+                .encode(env),
+            Err(ClientMessageError::MissingPayload) => {
+                (atoms::error(), atoms::missing_payload()).encode(env)
+            }
+        };
+        Ok(res)
     }
 
     pub fn tick<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
-        let diffs_iterator: ListIterator = args[0].decode()?;
+        let room = get_room(args[0])?;
         let update_all: bool = args[1].decode()?;
-        let delay_us: u64 = args[2].decode()?;
-
-        thread::sleep(Duration::from_micros(delay_us));
-
-        let diffs: Vec<InternalUserDiff> = diffs_iterator
-            .map(|diff| -> NifResult<UserDiff<'a>> { diff.decode() })
-            .map(
-                |diff_res: NifResult<UserDiff>| -> NifResult<InternalUserDiff> {
-                    match diff_res {
-                        Ok(diff) => diff.parse(env),
-                        Err(err) => Err(err),
-                    }
-                },
-            )
-            .collect::<NifResult<Vec<InternalUserDiff>>>()?;
+        let cur_tick: u32 = args[2].decode()?;
+
+        let messages = super::physics::server::tick(env, &room, cur_tick, update_all)?;
+
+        Ok(messages.encode(env))
+    }
+
+    /// Creates a new room with its own isolated physics world generated from the provided seed.
+    /// Creating a room that already exists is a no-op.
+    pub fn create_room<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+        let room_id: String = args[0].decode()?;
+        let seed: u64 = args[1].decode()?;
+
+        let created = ROOMS.create_room(room_id, seed);
+        Ok(created.encode(env))
+    }
 
-        let actions = super::physics::server::tick(env, update_all, diffs);
+    pub fn destroy_room<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+        let room_id: String = args[0].decode()?;
 
-        Ok(actions.encode(env))
+        let destroyed = ROOMS.destroy_room(&room_id);
+        Ok(destroyed.encode(env))
+    }
+
+    /// Saves the state of all entities in a room to a file.  Returns `:ok` or `{:error, reason}`.
+    pub fn save_room<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+        let room = get_room(args[0])?;
+        let path: String = args[1].decode()?;
+
+        let res = match super::physics::server::save_room(&room, &path) {
+            Ok(()) => atoms::ok().encode(env),
+            Err(err) => (atoms::error(), format!("{}", err)).encode(env),
+        };
+        Ok(res)
+    }
+
+    /// Creates a new room from a world saved with `save_room`.  Returns `true` if the room was
+    /// created, `false` if it already existed, or `{:error, reason}` if the save couldn't be
+    /// loaded.
+    pub fn load_room<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+        let room_id: String = args[0].decode()?;
+        let path: String = args[1].decode()?;
+
+        let res = match ROOMS.load_room(room_id, &path) {
+            Ok(created) => created.encode(env),
+            Err(err) => (atoms::error(), format!("{:?}", err)).encode(env),
+        };
+        Ok(res)
     }
 
     pub fn spawn_user<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
-        let uuid = args[0].decode()?;
+        let room = get_room(args[0])?;
+        let uuid = args[1].decode()?;
+        let cur_tick: u32 = args[2].decode()?;
 
-        let position = super::physics::server::spawn_user(uuid);
-        Ok(position.encode(env))
+        let spawned = super::physics::server::spawn_user(env, &room, uuid, cur_tick)?;
+        Ok(spawned.encode(env))
     }
 
     pub fn despawn_user<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
-        let uuid = args[0].decode()?;
+        let room = get_room(args[0])?;
+        let uuid = args[1].decode()?;
 
-        super::physics::server::despawn_user(uuid);
+        super::physics::server::despawn_user(&room, uuid);
         Ok(().encode(env))
     }
+
+    /// Turns gravitation between heavy objects on or off for a room
+    pub fn set_gravitation<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+        let room = get_room(args[0])?;
+        let enabled: bool = args[1].decode()?;
+
+        super::physics::server::set_gravitation(&room, enabled);
+        Ok(atoms::ok().encode(env))
+    }
 }
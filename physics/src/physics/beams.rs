@@ -0,0 +1,263 @@
+//! Implements players' beams.  A beam is a ray cast from the center of the player toward the point
+//! that they're aiming at, out to `CONF.game.player_beam_length`.  It hits the first entity along
+//! that ray.  The server and clients both use `cast_beam` so that they agree on what is being hit.
+
+use std::collections::BTreeMap;
+
+use nalgebra::{distance, Point2};
+use ncollide2d::query::Ray;
+use ncollide2d::world::CollisionGroups;
+
+use conf::CONF;
+
+use super::entities::{Entity, EntityHandles};
+use super::updates::WorldUpdate;
+use super::world::{EntityKey, PhysicsWorldInner};
+
+/// The result of casting a player's beam into the world
+#[derive(Clone, Debug, PartialEq)]
+pub struct BeamHit {
+    /// The entity that the beam hit first, or `None` if it didn't hit anything within range
+    pub target_id: Option<EntityKey>,
+    /// Where the beam hit its target or, if it missed, the end of the beam
+    pub point: Point2<f32>,
+}
+
+/// Builds the hit of a beam cast along `ray` from the time of impact with its nearest target, if
+/// it hit anything
+pub fn beam_hit(ray: &Ray<f32>, nearest_hit: Option<(EntityKey, f32)>) -> BeamHit {
+    match nearest_hit {
+        Some((target_id, toi)) => BeamHit {
+            target_id: Some(target_id),
+            point: ray.point_at(toi),
+        },
+        None => BeamHit {
+            target_id: None,
+            point: ray.point_at(CONF.game.player_beam_length),
+        },
+    }
+}
+
+impl<T> PhysicsWorldInner<T> {
+    /// Returns the ray that the given player's beam is cast along, starting at their center and
+    /// pointing toward their aim point with a unit direction.  Returns `None` if there's no such
+    /// player or they're aiming at their own center.
+    pub fn beam_ray(&self, player_id: &EntityKey) -> Option<Ray<f32>> {
+        let (body_handle, beam_aim) = match self.uuid_map.get(player_id)? {
+            EntityHandles {
+                body_handle,
+                entity: Entity::Player(player),
+                ..
+            } => (*body_handle, player.beam_aim),
+            _ => return None,
+        };
+        let origin = Point2::from_coordinates(
+            self.world.rigid_body(body_handle)?.position().translation.vector,
+        );
+
+        let offset = beam_aim - origin;
+        let aim_distance = offset.norm();
+        if aim_distance == 0. {
+            return None;
+        }
+        Some(Ray::new(origin, offset / aim_distance))
+    }
+
+    /// Casts the beam of the given player from their center toward their aim point and returns
+    /// what it hits.  Returns `None` if there's no such player or they're aiming at their own
+    /// center.
+    pub fn cast_beam(&self, player_id: &EntityKey) -> Option<BeamHit> {
+        let ray = self.beam_ray(player_id)?;
+        let beam_length = CONF.game.player_beam_length;
+
+        let nearest_hit = self
+            .world
+            .collision_world()
+            .interferences_with_ray(&ray, &CollisionGroups::new())
+            .filter(|(_, intersection)| intersection.toi <= beam_length)
+            .filter_map(|(object, intersection)| {
+                // Players can't hit themselves; ghost colliders map to the entities they copy
+                let target_id = self.handle_map.get(&object.handle())?;
+                if target_id == player_id {
+                    None
+                } else {
+                    Some((target_id, intersection.toi))
+                }
+            }).fold(None, |nearest: Option<(&EntityKey, f32)>, (target_id, toi)| {
+                match nearest {
+                    Some((_, nearest_toi)) if nearest_toi <= toi => nearest,
+                    _ => Some((target_id, toi)),
+                }
+            });
+
+        Some(beam_hit(
+            &ray,
+            nearest_hit.map(|(target_id, toi)| (target_id.clone(), toi)),
+        ))
+    }
+}
+
+impl PhysicsWorldInner<()> {
+    /// Casts the beams of all players that have them turned on, storing the results in
+    /// `beam_hits`.  Returns a `BeamHit` update for every beam that started hitting something
+    /// else or whose hit point moved by more than `CONF.network.movement_delta.position` since
+    /// the last one sent for it.
+    pub fn update_beam_hits(&mut self) -> Vec<WorldUpdate> {
+        let beaming_players: Vec<EntityKey> = self
+            .uuid_map
+            .iter()
+            .filter(|(_, handles)| match handles.entity {
+                Entity::Player(ref player) => player.beam_on,
+                _ => false,
+            }).map(|(id, _)| id.clone())
+            .collect();
+
+        let mut updates = Vec::new();
+        let mut beam_hits = BTreeMap::new();
+        for player_id in beaming_players {
+            // Beams are evaluated against the world that the player was seeing if we know it
+            let hit = match self.render_ticks.get(&player_id) {
+                Some(&render_tick) => self.cast_beam_at(&player_id, render_tick),
+                None => self.cast_beam(&player_id),
+            };
+            let hit = match hit {
+                Some(hit) => hit,
+                None => continue,
+            };
+
+            // Hits that haven't changed noticeably are kept as they were last sent
+            let hit = match self.beam_hits.remove(&player_id) {
+                Some(ref prev_hit)
+                    if prev_hit.target_id == hit.target_id
+                        && distance(&prev_hit.point, &hit.point)
+                            <= CONF.network.movement_delta.position =>
+                {
+                    prev_hit.clone()
+                }
+                _ => {
+                    updates.push(WorldUpdate::BeamHit {
+                        player_id: player_id.clone(),
+                        hit: hit.clone(),
+                    });
+                    hit
+                }
+            };
+            beam_hits.insert(player_id, hit);
+        }
+
+        self.beam_hits = beam_hits;
+        updates
+    }
+}
+
+#[test]
+fn beams_hit_the_nearest_entity_in_the_aim_direction() {
+    use nalgebra::{Isometry2, Vector2};
+    use nphysics2d::algebra::Velocity2;
+
+    use super::entities::ObjectShape;
+    use super::world::{spawn_test_object, uuid_to_key};
+
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    let spawn_asteroid = |world: &mut PhysicsWorldInner, x: f32| {
+        let square = vec![
+            Point2::new(-5., -5.),
+            Point2::new(5., -5.),
+            Point2::new(5., 5.),
+            Point2::new(-5., 5.),
+        ];
+        spawn_test_object(world, ObjectShape::Polygon(square), Vector2::new(x, 0.))
+    };
+    // One asteroid on either side of the player, with a farther one behind the one on the right
+    let (left_id, right_id) = (spawn_asteroid(&mut world, -30.), spawn_asteroid(&mut world, 30.));
+    spawn_asteroid(&mut world, 60.);
+
+    let player_uuid = world.gen_uuid();
+    let player_id = uuid_to_key(player_uuid);
+    world.spawn_player(player_uuid);
+    world.update_movement(
+        &player_id,
+        &Isometry2::identity(),
+        &Velocity2::zero(),
+        None,
+    );
+    world.world.step();
+
+    let aim_at = |world: &mut PhysicsWorldInner, x: f32| {
+        if let Some(EntityHandles {
+            entity: Entity::Player(player),
+            ..
+        }) = world.uuid_map.get_mut(&player_id)
+        {
+            player.beam_aim = Point2::new(x, 0.);
+        }
+        world.cast_beam(&player_id).unwrap()
+    };
+
+    let hit = aim_at(&mut world, 1000.);
+    assert_eq!(hit.target_id, Some(right_id));
+    assert!((hit.point.x - 25.).abs() < 0.5);
+    // The beam points away from the player toward the aim point, whichever quadrant it's in
+    assert_eq!(aim_at(&mut world, -1.).target_id, Some(left_id));
+}
+
+#[test]
+fn beams_reach_into_concave_entities() {
+    use nalgebra::{Isometry2, Vector2};
+    use nphysics2d::algebra::Velocity2;
+    use nphysics2d::object::BodyStatus;
+
+    use super::entities::{builtin_archetype, EntitySpawn, ObjectEntity, ObjectShape};
+    use super::world::uuid_to_key;
+
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    // A square with a notch cut into its left side that the beam can pass into
+    let notched_square = vec![
+        Point2::new(-10., -10.),
+        Point2::new(10., -10.),
+        Point2::new(10., 10.),
+        Point2::new(-10., 10.),
+        Point2::new(-10., 3.),
+        Point2::new(5., 3.),
+        Point2::new(5., -3.),
+        Point2::new(-10., -3.),
+    ];
+    let barrier_uuid = world.gen_uuid();
+    world.spawn_entity(
+        barrier_uuid,
+        EntitySpawn {
+            isometry: Isometry2::new(Vector2::new(30., 0.), 0.),
+            velocity: Velocity2::zero(),
+            entity: Entity::Object(ObjectEntity::new(
+                builtin_archetype("barrier"),
+                ObjectShape::Polygon(notched_square),
+            )),
+            data: (),
+            body_status: BodyStatus::Static,
+            material: None,
+        },
+    );
+
+    let player_uuid = world.gen_uuid();
+    let player_id = uuid_to_key(player_uuid);
+    world.spawn_player(player_uuid);
+    world.update_movement(
+        &player_id,
+        &Isometry2::identity(),
+        &Velocity2::zero(),
+        None,
+    );
+    if let Some(EntityHandles {
+        entity: Entity::Player(player),
+        ..
+    }) = world.uuid_map.get_mut(&player_id)
+    {
+        player.beam_aim = Point2::new(1000., 0.);
+    }
+    world.world.step();
+
+    let hit = world.cast_beam(&player_id).unwrap();
+    assert_eq!(hit.target_id, Some(uuid_to_key(barrier_uuid)));
+    // The beam hits the back of the notch rather than the outline's convex hull
+    assert!((hit.point.x - 35.).abs() < 0.5);
+}
@@ -0,0 +1,73 @@
+//! Helpers for reading and writing the little-endian binary formats used by recordings and saved
+//! worlds
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use uuid::Uuid;
+
+use super::Movement;
+
+/// Movements are stored as their index in this array
+pub const MOVEMENTS: [Movement; 9] = [
+    Movement::Stop,
+    Movement::Up,
+    Movement::UpRight,
+    Movement::Right,
+    Movement::DownRight,
+    Movement::Down,
+    Movement::DownLeft,
+    Movement::Left,
+    Movement::UpLeft,
+];
+
+pub fn write_u16<W: Write>(writer: &mut W, val: u16) -> io::Result<()> {
+    writer.write_all(&[val as u8, (val >> 8) as u8])
+}
+
+pub fn write_u32<W: Write>(writer: &mut W, val: u32) -> io::Result<()> {
+    write_u16(writer, val as u16)?;
+    write_u16(writer, (val >> 16) as u16)
+}
+
+pub fn write_u64<W: Write>(writer: &mut W, val: u64) -> io::Result<()> {
+    write_u32(writer, val as u32)?;
+    write_u32(writer, (val >> 32) as u32)
+}
+
+pub fn write_f32<W: Write>(writer: &mut W, val: f32) -> io::Result<()> {
+    write_u32(writer, val.to_bits())
+}
+
+pub fn write_uuid<W: Write>(writer: &mut W, id: &Uuid) -> io::Result<()> {
+    writer.write_all(id.as_bytes())
+}
+
+pub fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0] as u16 | (buf[1] as u16) << 8)
+}
+
+pub fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    Ok(read_u16(reader)? as u32 | (read_u16(reader)? as u32) << 16)
+}
+
+pub fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    Ok(read_u32(reader)? as u64 | (read_u32(reader)? as u64) << 32)
+}
+
+pub fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+    Ok(f32::from_bits(read_u32(reader)?))
+}
+
+pub fn read_uuid<R: Read>(reader: &mut R) -> io::Result<Uuid> {
+    let mut buf = [0u8; 16];
+    reader.read_exact(&mut buf)?;
+    Uuid::from_bytes(&buf).map_err(|_| io::Error::new(ErrorKind::InvalidData, "Invalid UUID"))
+}
@@ -1,23 +1,51 @@
 //! Contains definitions for the various types of entities that are spawnable into the world.
+//!
+//! Players are controlled by users and are special-cased throughout the engine.  Everything else
+//! is an object whose properties and behaviours are all described by an archetype defined in
+//! `config/entities.json`, so new kinds of objects can be added without code changes.
+
+use std::f32::consts::PI;
 
 use nalgebra::{Isometry2, Point2, Vector2};
-use ncollide2d::shape::{ConvexPolygon, Cuboid, ShapeHandle};
+use ncollide2d::shape::{Ball, Capsule, Compound, ConvexPolygon, Cuboid, ShapeHandle};
 use nphysics2d::algebra::Velocity2;
-use nphysics2d::object::{BodyHandle, BodyStatus, ColliderHandle, SensorHandle};
+use nphysics2d::object::{BodyHandle, BodyStatus, ColliderHandle, Material};
 
 use super::{world::COLLIDER_MARGIN, Movement};
-use conf::CONF;
+use conf::{ArchetypeConf, Behaviour, MaterialConf, CONF};
+use geometry::{area, convex_decomposition};
 
 pub const DEFAULT_PLAYER_SIZE: f32 = CONF.game.default_player_size;
+pub const PLAYER_ARCHETYPE: &str = "player";
+/// The number of vertices used to approximate each full circle in the outlines of round shapes
+const OUTLINE_CIRCLE_SEGMENTS: usize = 16;
+
+/// Returns the archetype with the given name, if there is one
+pub fn archetype(name: &str) -> Option<&'static ArchetypeConf> {
+    CONF.entities
+        .archetypes
+        .iter()
+        .find(|archetype| archetype.name == name)
+}
+
+/// Returns the archetype with the given name, panicking if it isn't defined.  Used for archetypes
+/// that the engine itself relies on.
+pub fn builtin_archetype(name: &str) -> &'static ArchetypeConf {
+    archetype(name).unwrap_or_else(|| panic!("No `{}` archetype in the entity config!", name))
+}
+
+impl ArchetypeConf {
+    pub fn has_behaviour(&self, behaviour: Behaviour) -> bool {
+        self.behaviours.contains(&behaviour)
+    }
 
-lazy_static! {
-    pub static ref BEAM_SHAPE_HANDLE: ShapeHandle<f32> = {
-        let shape = Cuboid::new(Vector2::new(
-            CONF.game.player_beam_length / 2. - COLLIDER_MARGIN,
-            CONF.game.player_beam_width / 2. - COLLIDER_MARGIN,
-        ));
-        ShapeHandle::new(shape)
-    };
+    pub fn body_status(&self) -> BodyStatus {
+        if self.is_static {
+            BodyStatus::Static
+        } else {
+            BodyStatus::Dynamic
+        }
+    }
 }
 
 pub fn create_player_shape_handle(size: f32) -> ShapeHandle<f32> {
@@ -28,20 +56,75 @@ pub fn create_player_shape_handle(size: f32) -> ShapeHandle<f32> {
     ShapeHandle::new(shape)
 }
 
+/// Creates a shape for an arbitrary simple polygon.  Convex polygons are used directly, and concave
+/// ones are decomposed into convex parts that are combined into a compound shape.
+pub fn create_polygon_shape_handle(vertices: &[Point2<f32>]) -> ShapeHandle<f32> {
+    let mut parts: Vec<ConvexPolygon<f32>> = convex_decomposition(vertices)
+        .into_iter()
+        .filter_map(ConvexPolygon::try_new)
+        .collect();
+
+    match parts.len() {
+        0 => {
+            println!(
+                "ERROR: Unable to create a shape for degenerate polygon {:?}",
+                vertices
+            );
+            ShapeHandle::new(Ball::new(COLLIDER_MARGIN))
+        }
+        1 => ShapeHandle::new(parts.pop().unwrap()),
+        _ => {
+            let shapes = parts
+                .into_iter()
+                .map(|part| (Isometry2::identity(), ShapeHandle::new(part)))
+                .collect();
+            ShapeHandle::new(Compound::new(shapes))
+        }
+    }
+}
+
+/// The physical properties of an entity.  Each type of entity has a default material set in the
+/// config, which can be overridden for individual entities when they're spawned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EntityMaterial {
+    pub restitution: f32,
+    pub friction: f32,
+    pub density: f32,
+}
+
+impl<'a> From<&'a MaterialConf> for EntityMaterial {
+    fn from(conf: &'a MaterialConf) -> Self {
+        EntityMaterial {
+            restitution: conf.restitution,
+            friction: conf.friction,
+            density: conf.density,
+        }
+    }
+}
+
+impl EntityMaterial {
+    /// Returns the material used by colliders with these properties
+    pub fn collider_material(&self) -> Material<f32> {
+        Material::new(self.restitution, self.friction)
+    }
+}
+
 pub struct EntitySpawn<T = ()> {
     pub isometry: Isometry2<f32>,
     pub velocity: Velocity2<f32>,
     pub entity: Entity,
     pub data: T,
     pub body_status: BodyStatus,
+    /// Overrides the default material of the entity's type if set
+    pub material: Option<EntityMaterial>,
 }
 
 pub struct EntityHandles<T> {
     pub collider_handle: ColliderHandle,
     pub body_handle: BodyHandle,
-    pub beam_handle: Option<SensorHandle>,
     pub entity: Entity,
     pub data: T,
+    pub material: EntityMaterial,
 }
 
 #[derive(Debug)]
@@ -50,6 +133,7 @@ pub struct PlayerEntity {
     pub movement: Movement,
     pub beam_aim: Point2<f32>,
     pub beam_on: bool,
+    pub health: f32,
 }
 
 impl PlayerEntity {
@@ -59,6 +143,7 @@ impl PlayerEntity {
             movement: Movement::default(),
             beam_aim: Point2::origin(),
             beam_on: false,
+            health: CONF.game.player_max_health,
         }
     }
 }
@@ -69,94 +154,118 @@ impl Default for PlayerEntity {
     }
 }
 
-#[derive(Debug)]
-pub struct AsteroidEntity {
-    pub vertices: Vec<Point2<f32>>,
-}
-
-#[derive(Debug)]
-pub struct BarrierEntity {
-    pub vertices: Vec<Point2<f32>>,
+/// The shape of an object, centered around its origin
+#[derive(Clone, Debug, PartialEq)]
+pub enum ObjectShape {
+    /// A simple polygon, which may be concave
+    Polygon(Vec<Point2<f32>>),
+    Circle { radius: f32 },
+    /// A rectangle of height `2 * half_height` along the y axis capped by semicircles of `radius`
+    Capsule { half_height: f32, radius: f32 },
 }
 
-#[derive(Debug)]
-pub enum Entity {
-    Player(PlayerEntity),
-    Asteroid(AsteroidEntity),
-    Barrier(BarrierEntity),
-}
+impl ObjectShape {
+    pub fn area(&self) -> f32 {
+        match self {
+            ObjectShape::Polygon(vertices) => area(vertices),
+            ObjectShape::Circle { radius } => PI * radius * radius,
+            ObjectShape::Capsule {
+                half_height,
+                radius,
+            } => 4. * half_height * radius + PI * radius * radius,
+        }
+    }
 
-impl Entity {
-    #[cfg(feature = "elixir-interop")]
-    pub fn to_data<'a>(
-        &self,
-        env: rustler::Env<'a>,
-    ) -> rustler::NifResult<(rustler::types::atom::Atom, rustler::Term<'a>)> {
-        use rustler::{types::atom::Atom, Encoder, NifResult, Term};
-
-        use super::super::atoms;
-
-        let make_map = |items: &[(Atom, &Encoder)]| -> NifResult<Term<'a>> {
-            let mut map = Term::map_new(env);
-            for (key, val) in items {
-                map = map.map_put(key.encode(env), val.encode(env))?;
-            }
-            Ok(map)
-        };
+    pub fn shape_handle(&self) -> ShapeHandle<f32> {
+        match self {
+            ObjectShape::Polygon(vertices) => create_polygon_shape_handle(vertices),
+            ObjectShape::Circle { radius } => ShapeHandle::new(Ball::new(*radius)),
+            ObjectShape::Capsule {
+                half_height,
+                radius,
+            } => ShapeHandle::new(Capsule::new(*half_height, *radius)),
+        }
+    }
 
-        let make_vert_map = |verts: &[Point2<f32>]| -> NifResult<Term<'a>> {
-            let map = Term::map_new(env);
-            let mut mapped_verts: Vec<f32> = Vec::with_capacity(verts.len() * 2);
-            for vert in verts {
-                mapped_verts.push(vert.x);
-                mapped_verts.push(vert.y);
-            }
-            map.map_put(atoms::vert_coords().encode(env), mapped_verts.encode(env))
+    /// Returns the outline of the shape as a counter-clockwise polygon, approximating round
+    /// shapes with `OUTLINE_CIRCLE_SEGMENTS` vertices per circle
+    pub fn outline(&self) -> Vec<Point2<f32>> {
+        let step = 2. * PI / OUTLINE_CIRCLE_SEGMENTS as f32;
+        let arc_point = |center: Point2<f32>, radius: f32, angle: f32| {
+            center + Vector2::new(angle.cos(), angle.sin()) * radius
         };
 
         match self {
-            Entity::Player(PlayerEntity {
-                size,
-                movement,
-                beam_aim,
-                beam_on,
-            }) => {
-                let movement_atom: Atom = (*movement).into();
-                let map = make_map(&[
-                    (atoms::size(), size),
-                    (atoms::movement(), &movement_atom),
-                    (atoms::beam_aim(), &(beam_aim.x, beam_aim.y)),
-                    (atoms::beam_on(), beam_on),
-                ])?;
-
-                Ok((atoms::player(), map))
-            }
-            Entity::Asteroid(AsteroidEntity { vertices }) => {
-                Ok((atoms::asteroid(), make_vert_map(&vertices)?))
-            }
-            Entity::Barrier(BarrierEntity { vertices }) => {
-                Ok((atoms::barrier(), make_vert_map(&vertices)?))
+            ObjectShape::Polygon(vertices) => vertices.clone(),
+            ObjectShape::Circle { radius } => (0..OUTLINE_CIRCLE_SEGMENTS)
+                .map(|i| arc_point(Point2::origin(), *radius, i as f32 * step))
+                .collect(),
+            ObjectShape::Capsule {
+                half_height,
+                radius,
+            } => {
+                // Each cap includes both of its ends, which are joined by the capsule's sides
+                let cap_segments = OUTLINE_CIRCLE_SEGMENTS / 2;
+                let (top, bottom) = (Point2::new(0., *half_height), Point2::new(0., -half_height));
+                let top_cap = (0..=cap_segments).map(|i| arc_point(top, *radius, i as f32 * step));
+                let bottom_cap =
+                    (0..=cap_segments).map(|i| arc_point(bottom, *radius, PI + i as f32 * step));
+                top_cap.chain(bottom_cap).collect()
             }
         }
     }
+}
+
+/// An entity whose properties all come from its archetype, such as an asteroid or a barrier
+#[derive(Debug)]
+pub struct ObjectEntity {
+    pub archetype: &'static ArchetypeConf,
+    pub shape: ObjectShape,
+    /// The amount of material left in the object.  Mineable objects lose health while players'
+    /// beams hit them and are destroyed once it runs out.
+    pub health: f32,
+}
 
+impl ObjectEntity {
+    /// Creates a new object of the given archetype with full health
+    pub fn new(archetype: &'static ArchetypeConf, shape: ObjectShape) -> Self {
+        let health = shape.area() * archetype.health_per_area;
+        ObjectEntity {
+            archetype,
+            shape,
+            health,
+        }
+    }
+
+    /// The health that a fresh object of this shape and archetype has
+    pub fn max_health(&self) -> f32 {
+        self.shape.area() * self.archetype.health_per_area
+    }
+}
+
+#[derive(Debug)]
+pub enum Entity {
+    Player(PlayerEntity),
+    Object(ObjectEntity),
+}
+
+impl Entity {
     pub fn get_shape_handle(&self) -> ShapeHandle<f32> {
         match self {
             Entity::Player(PlayerEntity { size, .. }) => create_player_shape_handle(*size as f32),
-            Entity::Asteroid(AsteroidEntity { vertices })
-            | Entity::Barrier(BarrierEntity { vertices }) => {
-                let shape = ConvexPolygon::try_new(vertices.clone())
-                    .expect("Unable to compute `ConvexPolygon` from asteroid vertices!");
-                ShapeHandle::new(shape)
-            }
+            Entity::Object(ObjectEntity { shape, .. }) => shape.shape_handle(),
         }
     }
 
-    pub fn get_density(&self) -> f32 {
+    pub fn archetype(&self) -> &'static ArchetypeConf {
         match self {
-            Entity::Player { .. } => 1.0,
-            Entity::Asteroid { .. } => 3.5,
-            Entity::Barrier { .. } => 10.0,
+            Entity::Player(_) => builtin_archetype(PLAYER_ARCHETYPE),
+            Entity::Object(ObjectEntity { archetype, .. }) => archetype,
         }
     }
+
+    /// Returns the material configured for this entity's archetype
+    pub fn default_material(&self) -> EntityMaterial {
+        (&self.archetype().material).into()
+    }
 }
@@ -0,0 +1,244 @@
+//! Implements force fields: circular regions of the world that push dynamic bodies around.
+//! Gravity wells pull bodies toward their centers, repulsors push them away, and currents push
+//! them in a fixed direction.  Fields are placed randomly when worlds are generated and can also
+//! be loaded from a map file.
+//!
+//! Map files are plain text with one field per line.  Blank lines and lines starting with `#` are
+//! ignored.  Each line holds the kind of field followed by its center, radius, and parameters:
+//!
+//! ```text
+//! gravity_well <x> <y> <radius> <strength>
+//! repulsor <x> <y> <radius> <strength>
+//! current <x> <y> <radius> <acceleration x> <acceleration y>
+//! ```
+//!
+//! Strengths are the accelerations that fields apply at their centers in units per tick per tick.
+//! Gravity wells and repulsors weaken linearly toward their edges, while currents are uniform.
+//! Since accelerations are applied regardless of mass, light and heavy bodies move alike.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use nalgebra::{distance_squared, Point2, Vector2};
+use nphysics2d::algebra::Velocity2;
+use nphysics2d::force_generator::ForceGenerator;
+use nphysics2d::object::{BodyHandle, BodySet, BodyStatus};
+use nphysics2d::solver::IntegrationParameters;
+
+use super::world::PhysicsWorldInner;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForceFieldKind {
+    GravityWell { strength: f32 },
+    Repulsor { strength: f32 },
+    Current { acceleration: Vector2<f32> },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ForceField {
+    pub center: Point2<f32>,
+    pub radius: f32,
+    pub kind: ForceFieldKind,
+}
+
+impl ForceField {
+    pub fn contains(&self, pos: &Point2<f32>) -> bool {
+        distance_squared(&self.center, pos) <= self.radius * self.radius
+    }
+
+    /// Returns the acceleration that the field applies to a body at `pos`
+    pub fn acceleration_at(&self, pos: &Point2<f32>) -> Vector2<f32> {
+        if !self.contains(pos) {
+            return Vector2::zeros();
+        }
+
+        match self.kind {
+            ForceFieldKind::GravityWell { strength } => self.radial_acceleration(pos, strength),
+            ForceFieldKind::Repulsor { strength } => -self.radial_acceleration(pos, strength),
+            ForceFieldKind::Current { acceleration } => acceleration,
+        }
+    }
+
+    /// Returns an acceleration toward the center of the field that is `strength` at the center
+    /// and falls off linearly to nothing at its edge
+    fn radial_acceleration(&self, pos: &Point2<f32>, strength: f32) -> Vector2<f32> {
+        let offset = self.center - pos;
+        let distance = offset.norm();
+        // Bodies exactly at the center are pulled equally in all directions
+        if distance == 0. {
+            return Vector2::zeros();
+        }
+
+        offset / distance * strength * (1. - distance / self.radius)
+    }
+}
+
+/// Applies all of the world's force fields to the bodies within them.  Like
+/// `PlayerMovementForceGenerator`, it is re-created every tick, here with the handles of all of
+/// the bodies that are inside of a field.
+pub struct ForceFieldGenerator {
+    fields: Vec<ForceField>,
+    body_handles: Vec<BodyHandle>,
+}
+
+impl ForceGenerator<f32> for ForceFieldGenerator {
+    fn apply(&mut self, _: &IntegrationParameters<f32>, bodies: &mut BodySet<f32>) -> bool {
+        for body_handle in &self.body_handles {
+            let mut part = bodies.body_part_mut(*body_handle);
+            let pos = Point2::from_coordinates(part.as_ref().position().translation.vector);
+            let acceleration = self
+                .fields
+                .iter()
+                .fold(Vector2::zeros(), |acc, field| acc + field.acceleration_at(&pos));
+
+            let force = part.as_ref().inertia() * Velocity2::new(acceleration, 0.);
+            part.apply_force(&force);
+        }
+
+        true
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    /// The line with the given number, starting from 1, isn't a valid force field
+    Malformed(usize),
+}
+
+impl From<io::Error> for MapError {
+    fn from(err: io::Error) -> Self {
+        MapError::Io(err)
+    }
+}
+
+fn parse_force_field(line: &str) -> Option<ForceField> {
+    let mut tokens = line.split_whitespace();
+    let kind = tokens.next()?;
+    let vals: Vec<f32> = tokens.map(|token| token.parse().ok()).collect::<Option<_>>()?;
+
+    let kind = match (kind, vals.len()) {
+        ("gravity_well", 4) => ForceFieldKind::GravityWell { strength: vals[3] },
+        ("repulsor", 4) => ForceFieldKind::Repulsor { strength: vals[3] },
+        ("current", 5) => ForceFieldKind::Current {
+            acceleration: Vector2::new(vals[3], vals[4]),
+        },
+        _ => return None,
+    };
+    if vals[2] <= 0. {
+        return None;
+    }
+
+    Some(ForceField {
+        center: Point2::new(vals[0], vals[1]),
+        radius: vals[2],
+        kind,
+    })
+}
+
+/// Parses the force fields out of the contents of a map file
+pub fn parse_map(map: &str) -> Result<Vec<ForceField>, MapError> {
+    map.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| parse_force_field(line).ok_or(MapError::Malformed(i + 1)))
+        .collect()
+}
+
+/// Reads the force fields defined in the map file at `path`
+pub fn load_map<P: AsRef<Path>>(path: P) -> Result<Vec<ForceField>, MapError> {
+    parse_map(&fs::read_to_string(path)?)
+}
+
+impl<T> PhysicsWorldInner<T> {
+    /// Replaces the force generator for the world's force fields with one that affects all of the
+    /// dynamic bodies that are currently inside of them.  Bodies in fields are woken up so that
+    /// the fields can move them even if they have come to rest.
+    pub fn update_force_fields(&mut self) {
+        if let Some(handle) = self.force_field_generator.take() {
+            self.world.remove_force_generator(handle);
+        }
+        if self.force_fields.is_empty() {
+            return;
+        }
+
+        let mut body_handles = Vec::new();
+        for handles in self.uuid_map.values() {
+            let body = match self.world.rigid_body_mut(handles.body_handle) {
+                Some(body) if body.status() == BodyStatus::Dynamic => body,
+                _ => continue,
+            };
+            let pos = Point2::from_coordinates(body.position().translation.vector);
+            if self.force_fields.iter().any(|field| field.contains(&pos)) {
+                body.activate();
+                body_handles.push(handles.body_handle);
+            }
+        }
+
+        let generator = ForceFieldGenerator {
+            fields: self.force_fields.clone(),
+            body_handles,
+        };
+        self.force_field_generator = Some(self.world.add_force_generator(generator));
+    }
+}
+
+#[test]
+fn map_files_are_parsed() {
+    let map = "
+        # A well with a current flowing past it
+        gravity_well 100 -50 200 0.01
+
+        current 0 0 50.5 0.5 -0.25
+    ";
+    assert_eq!(
+        parse_map(map).unwrap(),
+        vec![
+            ForceField {
+                center: Point2::new(100., -50.),
+                radius: 200.,
+                kind: ForceFieldKind::GravityWell { strength: 0.01 },
+            },
+            ForceField {
+                center: Point2::origin(),
+                radius: 50.5,
+                kind: ForceFieldKind::Current {
+                    acceleration: Vector2::new(0.5, -0.25),
+                },
+            },
+        ]
+    );
+
+    match parse_map("repulsor 0 0 10 1\nrepulsor 0 0 10") {
+        Err(MapError::Malformed(2)) => (),
+        res => panic!("Expected the second line to be malformed; got {:?}", res),
+    }
+}
+
+#[test]
+fn gravity_wells_pull_bodies_toward_their_centers() {
+    use super::entities::ObjectShape;
+    use super::world::spawn_test_object;
+
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    world.force_fields.push(ForceField {
+        center: Point2::origin(),
+        radius: 100.,
+        kind: ForceFieldKind::GravityWell { strength: 0.05 },
+    });
+    let id = spawn_test_object(
+        &mut world,
+        ObjectShape::Circle { radius: 5. },
+        Vector2::new(50., 0.),
+    );
+
+    for _ in 0..10 {
+        world.step();
+    }
+    let (movement, _) = world.entity_state(&id).unwrap();
+    assert!(movement.pos_x < 50.);
+    assert!(movement.velocity_x < 0.);
+    assert!(movement.pos_y.abs() < 0.001);
+}
@@ -0,0 +1,191 @@
+//! Implements the breaking up of fracturable objects such as asteroids into smaller fragments,
+//! either when they are depleted by mining or when they are struck hard enough by another entity.
+
+use std::f32::consts::PI;
+
+use nalgebra::{Isometry2, Point2, Vector2};
+use nphysics2d::algebra::Velocity2;
+use rand::Rng;
+
+use conf::{Behaviour, CONF};
+use geometry::{area, centroid, convex_decomposition, split_convex_polygon};
+
+use super::entities::{Entity, EntityHandles, EntitySpawn, ObjectEntity, ObjectShape};
+use super::world::{EntityKey, PhysicsWorldInner};
+
+/// Cuts a simple polygon into up to `count` convex pieces.  Concave polygons are first decomposed
+/// into convex parts, and then the largest remaining piece is repeatedly split through its
+/// centroid along a randomly oriented line.
+pub fn fracture_polygon<R: Rng>(
+    rng: &mut R,
+    vertices: &[Point2<f32>],
+    count: usize,
+) -> Vec<Vec<Point2<f32>>> {
+    let mut pieces = convex_decomposition(vertices);
+
+    while !pieces.is_empty() && pieces.len() < count {
+        let (largest_ix, _) = pieces.iter().enumerate().fold(
+            (0, 0.0f32),
+            |(max_ix, max_area), (i, piece)| {
+                let piece_area = area(piece);
+                if piece_area > max_area {
+                    (i, piece_area)
+                } else {
+                    (max_ix, max_area)
+                }
+            },
+        );
+
+        let angle = rng.gen_range(0., PI);
+        let dir = Vector2::new(angle.cos(), angle.sin());
+        let (left, right) =
+            split_convex_polygon(&pieces[largest_ix], &centroid(&pieces[largest_ix]), &dir);
+        if left.len() < 3 || right.len() < 3 {
+            break;
+        }
+
+        pieces[largest_ix] = left;
+        pieces.push(right);
+    }
+
+    pieces
+}
+
+impl PhysicsWorldInner<()> {
+    /// Removes the object with the given ID from the world and replaces it with fragments of the
+    /// same archetype that inherit its momentum, angular velocity, and material.  Fragments that
+    /// are too small are discarded.  Each fragment starts with `health_ratio` times the health of
+    /// a fresh object of its size.  Round objects are fractured along polygonal approximations
+    /// of their outlines.
+    pub fn fracture_object(&mut self, id: &EntityKey, health_ratio: f32) {
+        let (archetype, outline, material, isometry, velocity, center_of_mass) = {
+            let (archetype, outline, material, body_handle) = match self.uuid_map.get(id) {
+                Some(EntityHandles {
+                    entity: Entity::Object(ObjectEntity {
+                        archetype, shape, ..
+                    }),
+                    material,
+                    body_handle,
+                    ..
+                }) => (*archetype, shape.outline(), *material, *body_handle),
+                _ => {
+                    println!("ERROR: Tried to fracture an entity that isn't an object");
+                    return;
+                }
+            };
+            let body = match self.world.rigid_body(body_handle) {
+                Some(body) => body,
+                None => {
+                    println!("ERROR: Tried to fracture an object that isn't a rigid body");
+                    return;
+                }
+            };
+
+            (
+                archetype,
+                outline,
+                material,
+                *body.position(),
+                *body.velocity(),
+                body.center_of_mass(),
+            )
+        };
+
+        self.remove_entity(id);
+
+        let pieces = fracture_polygon(
+            &mut self.rng,
+            &outline,
+            CONF.game.asteroid_fracture.fragment_count,
+        );
+        for piece in pieces {
+            if area(&piece) < CONF.game.asteroid_fracture.min_fragment_area {
+                continue;
+            }
+
+            // Re-center the fragment's vertices around its own centroid
+            let piece_center = centroid(&piece);
+            let fragment_vertices = piece
+                .iter()
+                .map(|vert| Point2::from_coordinates(*vert - piece_center))
+                .collect();
+            let fragment_pos = isometry * piece_center;
+
+            // Points on a rotating body move tangentially to it relative to its center of mass
+            let offset = fragment_pos - center_of_mass;
+            let tangential_velocity = Vector2::new(-offset.y, offset.x) * velocity.angular;
+
+            let mut fragment =
+                ObjectEntity::new(archetype, ObjectShape::Polygon(fragment_vertices));
+            fragment.health *= health_ratio;
+
+            let fragment_id = self.gen_uuid();
+            self.spawn_entity(
+                fragment_id,
+                EntitySpawn {
+                    isometry: Isometry2::new(fragment_pos.coords, isometry.rotation.angle()),
+                    velocity: Velocity2::new(
+                        velocity.linear + tangential_velocity,
+                        velocity.angular,
+                    ),
+                    entity: Entity::Object(fragment),
+                    data: (),
+                    body_status: archetype.body_status(),
+                    material: Some(material),
+                },
+            );
+        }
+    }
+
+    /// Fractures all fracturable objects that were struck hard enough during the last step, given
+    /// the impacts returned by `PhysicsWorldInner::impacts`.  Fragments keep the same proportion
+    /// of health that the original object had left.
+    pub fn fracture_impacted_objects(&mut self, impacts: &[(EntityKey, f32)]) {
+        let impacted: Vec<(EntityKey, f32)> = impacts
+            .iter()
+            .filter(|(_, velocity_change)| {
+                *velocity_change >= CONF.game.asteroid_fracture.impact_velocity_change
+            }).filter_map(|(id, _)| match self.uuid_map.get(id) {
+                Some(EntityHandles {
+                    entity: Entity::Object(object),
+                    ..
+                })
+                    if object.archetype.has_behaviour(Behaviour::Fracturable) =>
+                {
+                    // Objects without health are always fractured into fully intact fragments
+                    let max_health = object.max_health();
+                    let health_ratio = if max_health > 0. {
+                        object.health / max_health
+                    } else {
+                        1.0
+                    };
+                    Some((id.clone(), health_ratio))
+                }
+                _ => None,
+            }).collect();
+
+        for (id, health_ratio) in impacted {
+            self.fracture_object(&id, health_ratio);
+        }
+    }
+}
+
+#[test]
+fn fragments_cover_the_original_polygon() {
+    use geometry::signed_area;
+    use worldgen::rng_from_seed;
+
+    let hexagon: Vec<Point2<f32>> = (0..6)
+        .map(|i| {
+            let angle = i as f32 * PI / 3.;
+            Point2::new(angle.cos() * 10., angle.sin() * 10.)
+        }).collect();
+    let pieces = fracture_polygon(&mut rng_from_seed(7), &hexagon, 4);
+
+    assert_eq!(pieces.len(), 4);
+    let total_area: f32 = pieces.iter().map(|piece| area(piece)).sum();
+    assert!((total_area - area(&hexagon)).abs() < 0.01);
+    for piece in &pieces {
+        assert!(signed_area(piece) > 0.);
+    }
+}
@@ -0,0 +1,379 @@
+//! Implements optional n-body gravitation: objects heavier than
+//! `CONF.physics.gravitation.min_attractor_mass` attract each other and all players.  It can be
+//! turned on and off for each room, and starts out as `enabled_by_default`.
+//!
+//! Pulling every body toward every attractor directly is quadratic in the number of attractors, so
+//! the attractors are sorted into a quadtree every step and distant groups of them are treated as a
+//! single body at their combined center of mass (the Barnes–Hut approximation).  How far away a
+//! group has to be for that is controlled by `barnes_hut_theta`.
+
+use nalgebra::{distance, Point2, Vector2};
+use nphysics2d::algebra::Velocity2;
+use nphysics2d::force_generator::ForceGenerator;
+use nphysics2d::object::{BodyHandle, BodySet, BodyStatus};
+use nphysics2d::solver::IntegrationParameters;
+
+use conf::CONF;
+
+use super::entities::{Entity, ObjectEntity};
+use super::world::PhysicsWorldInner;
+
+/// Nodes this deep in the tree aren't split any further, so that bodies at (nearly) the same
+/// position don't cause the tree to be subdivided forever.
+const MAX_TREE_DEPTH: usize = 16;
+
+/// A body that attracts others, given by its center of mass and its mass
+pub type Attractor = (Point2<f32>, f32);
+
+struct QuadTreeNode {
+    center: Point2<f32>,
+    half_size: f32,
+    mass: f32,
+    center_of_mass: Point2<f32>,
+    /// Indices of the non-empty child nodes, or an empty list for leaves
+    children: Vec<usize>,
+    /// Indices of the attractors within leaves, or an empty list for inner nodes
+    attractors: Vec<usize>,
+}
+
+impl QuadTreeNode {
+    fn contains(&self, pos: &Point2<f32>) -> bool {
+        (pos.x - self.center.x).abs() <= self.half_size
+            && (pos.y - self.center.y).abs() <= self.half_size
+    }
+}
+
+/// Parameters for computing gravitational accelerations
+#[derive(Clone, Copy, Debug)]
+pub struct GravitationParams {
+    pub gravitational_constant: f32,
+    pub softening_distance: f32,
+    pub theta: f32,
+}
+
+impl Default for GravitationParams {
+    fn default() -> Self {
+        let conf = &CONF.physics.gravitation;
+        GravitationParams {
+            gravitational_constant: conf.gravitational_constant,
+            softening_distance: conf.softening_distance,
+            theta: conf.barnes_hut_theta,
+        }
+    }
+}
+
+impl GravitationParams {
+    /// Returns the acceleration of a body at `pos` toward a mass of `mass` at `attractor_pos`
+    fn pull(&self, pos: &Point2<f32>, attractor_pos: &Point2<f32>, mass: f32) -> Vector2<f32> {
+        let offset = attractor_pos - pos;
+        let softened_distance_squared =
+            offset.norm_squared() + self.softening_distance * self.softening_distance;
+
+        let softened_distance_cubed = softened_distance_squared * softened_distance_squared.sqrt();
+        offset * (self.gravitational_constant * mass / softened_distance_cubed)
+    }
+}
+
+/// A Barnes–Hut quadtree holding the masses of a set of attractors
+pub struct QuadTree<'a> {
+    attractors: &'a [Attractor],
+    /// The root node comes first
+    nodes: Vec<QuadTreeNode>,
+}
+
+impl<'a> QuadTree<'a> {
+    pub fn new(attractors: &'a [Attractor]) -> Self {
+        let mut tree = QuadTree {
+            attractors,
+            nodes: Vec::new(),
+        };
+        if attractors.is_empty() {
+            return tree;
+        }
+
+        let (mut min, mut max) = (attractors[0].0, attractors[0].0);
+        for (pos, _) in attractors {
+            min = Point2::new(min.x.min(pos.x), min.y.min(pos.y));
+            max = Point2::new(max.x.max(pos.x), max.y.max(pos.y));
+        }
+        let center = Point2::from_coordinates((min.coords + max.coords) / 2.);
+        let half_size = ((max.x - min.x).max(max.y - min.y) / 2.).max(1.);
+
+        tree.build((0..attractors.len()).collect(), center, half_size, 0);
+        tree
+    }
+
+    /// Adds a node containing the attractors with the given indices to the tree along with all of
+    /// its descendants, returning its index.
+    fn build(
+        &mut self,
+        indices: Vec<usize>,
+        center: Point2<f32>,
+        half_size: f32,
+        depth: usize,
+    ) -> usize {
+        let mass: f32 = indices.iter().map(|&i| self.attractors[i].1).sum();
+        let weighted_pos = indices.iter().fold(Vector2::zeros(), |acc, &i| {
+            let (pos, attractor_mass) = self.attractors[i];
+            acc + pos.coords * attractor_mass
+        });
+        let center_of_mass = if mass > 0. {
+            Point2::from_coordinates(weighted_pos / mass)
+        } else {
+            center
+        };
+
+        let node_ix = self.nodes.len();
+        self.nodes.push(QuadTreeNode {
+            center,
+            half_size,
+            mass,
+            center_of_mass,
+            children: Vec::new(),
+            attractors: Vec::new(),
+        });
+        if indices.len() <= 1 || depth >= MAX_TREE_DEPTH {
+            self.nodes[node_ix].attractors = indices;
+            return node_ix;
+        }
+
+        let mut quadrants = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
+        for i in indices {
+            let pos = self.attractors[i].0;
+            let quadrant = (pos.x >= center.x) as usize + 2 * (pos.y >= center.y) as usize;
+            quadrants[quadrant].push(i);
+        }
+
+        let child_half_size = half_size / 2.;
+        for (quadrant, indices) in quadrants.iter_mut().enumerate() {
+            if indices.is_empty() {
+                continue;
+            }
+
+            let x_sign = if quadrant & 1 == 0 { -1. } else { 1. };
+            let y_sign = if quadrant & 2 == 0 { -1. } else { 1. };
+            let child_center = center + Vector2::new(x_sign, y_sign) * child_half_size;
+            let child_ix = self.build(
+                ::std::mem::replace(indices, Vec::new()),
+                child_center,
+                child_half_size,
+                depth + 1,
+            );
+            self.nodes[node_ix].children.push(child_ix);
+        }
+
+        node_ix
+    }
+
+    /// Returns the acceleration of a body at `pos` caused by all of the attractors in the tree
+    /// except the one with index `exclude`, which is the body itself if it's an attractor.
+    pub fn acceleration_at(
+        &self,
+        pos: &Point2<f32>,
+        exclude: Option<usize>,
+        params: &GravitationParams,
+    ) -> Vector2<f32> {
+        let mut acceleration = Vector2::zeros();
+        if self.nodes.is_empty() {
+            return acceleration;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node_ix) = stack.pop() {
+            let node = &self.nodes[node_ix];
+            if node.children.is_empty() {
+                for &i in &node.attractors {
+                    if Some(i) != exclude {
+                        let (attractor_pos, mass) = self.attractors[i];
+                        acceleration += params.pull(pos, &attractor_pos, mass);
+                    }
+                }
+                continue;
+            }
+
+            // Nodes containing the body are always opened so that it never pulls on itself
+            let size = node.half_size * 2.;
+            let far_enough = size < params.theta * distance(pos, &node.center_of_mass);
+            if far_enough && !node.contains(pos) {
+                acceleration += params.pull(pos, &node.center_of_mass, node.mass);
+            } else {
+                stack.extend_from_slice(&node.children);
+            }
+        }
+
+        acceleration
+    }
+}
+
+/// Applies gravitation to all attractors and the players around them.  Like
+/// `ForceFieldGenerator`, it is re-created every tick with the handles of the bodies it affects.
+pub struct GravitationForceGenerator {
+    attractors: Vec<BodyHandle>,
+    satellites: Vec<BodyHandle>,
+    params: GravitationParams,
+}
+
+impl ForceGenerator<f32> for GravitationForceGenerator {
+    fn apply(&mut self, _: &IntegrationParameters<f32>, bodies: &mut BodySet<f32>) -> bool {
+        let attractors: Vec<Attractor> = self
+            .attractors
+            .iter()
+            .map(|handle| {
+                let part = bodies.body_part(*handle);
+                (part.center_of_mass(), part.inertia().linear)
+            })
+            .collect();
+        let tree = QuadTree::new(&attractors);
+
+        let pulled_bodies = self
+            .attractors
+            .iter()
+            .enumerate()
+            .map(|(i, handle)| (handle, Some(i)))
+            .chain(self.satellites.iter().map(|handle| (handle, None)));
+        for (body_handle, exclude) in pulled_bodies {
+            let mut part = bodies.body_part_mut(*body_handle);
+            let pos = part.as_ref().center_of_mass();
+            let acceleration = tree.acceleration_at(&pos, exclude, &self.params);
+
+            let force = part.as_ref().inertia() * Velocity2::new(acceleration, 0.);
+            part.apply_force(&force);
+        }
+
+        true
+    }
+}
+
+impl<T> PhysicsWorldInner<T> {
+    /// Turns gravitation on or off for this world.  It takes effect during the next step.
+    pub fn set_gravitation(&mut self, enabled: bool) {
+        self.record(|recorder| recorder.record_gravitation(enabled));
+        self.gravitation_enabled = enabled;
+        if !enabled {
+            if let Some(handle) = self.gravitation_generator.take() {
+                self.world.remove_force_generator(handle);
+            }
+        }
+    }
+
+    /// Replaces the force generator for gravitation with one that affects all of the current
+    /// attractors and players if gravitation is enabled.  The affected bodies are woken up so
+    /// that they are pulled around even if they have come to rest.
+    pub fn update_gravitation(&mut self) {
+        if let Some(handle) = self.gravitation_generator.take() {
+            self.world.remove_force_generator(handle);
+        }
+        if !self.gravitation_enabled {
+            return;
+        }
+
+        let (mut attractors, mut satellites) = (Vec::new(), Vec::new());
+        for handles in self.uuid_map.values() {
+            match handles.entity {
+                Entity::Player(_) => satellites.push(handles.body_handle),
+                Entity::Object(ObjectEntity { ref shape, .. })
+                    if shape.area() * handles.material.density
+                        >= CONF.physics.gravitation.min_attractor_mass =>
+                {
+                    attractors.push(handles.body_handle)
+                }
+                Entity::Object(_) => (),
+            }
+        }
+        attractors.retain(|handle| match self.world.rigid_body(*handle) {
+            Some(body) => body.status() == BodyStatus::Dynamic,
+            None => false,
+        });
+        if attractors.is_empty() {
+            return;
+        }
+
+        for handle in attractors.iter().chain(satellites.iter()) {
+            if let Some(body) = self.world.rigid_body_mut(*handle) {
+                body.activate();
+            }
+        }
+
+        let generator = GravitationForceGenerator {
+            attractors,
+            satellites,
+            params: GravitationParams::default(),
+        };
+        self.gravitation_generator = Some(self.world.add_force_generator(generator));
+    }
+}
+
+#[test]
+fn barnes_hut_approximates_direct_summation() {
+    let attractors: Vec<Attractor> = (0..200)
+        .map(|i| {
+            let i = i as f32;
+            let pos = Point2::new((i * 37.).sin() * 500., (i * 91.).cos() * 500.);
+            (pos, 1000. + (i * 13.) % 700.)
+        })
+        .collect();
+    let tree = QuadTree::new(&attractors);
+    let params = GravitationParams {
+        gravitational_constant: 0.02,
+        softening_distance: 20.,
+        theta: 0.5,
+    };
+
+    // Pulls from all sides mostly cancel out, so errors are compared to the sizes of the pulls
+    for (i, (pos, _)) in attractors.iter().enumerate().step_by(17) {
+        let (mut exact, mut total_pull) = (Vector2::zeros(), 0.);
+        for (j, (attractor_pos, mass)) in attractors.iter().enumerate() {
+            if i != j {
+                let pull = params.pull(pos, attractor_pos, *mass);
+                exact += pull;
+                total_pull += pull.norm();
+            }
+        }
+        let approximate = tree.acceleration_at(pos, Some(i), &params);
+        assert!((approximate - exact).norm() <= total_pull * 0.01);
+    }
+
+    // With a theta of 0, no groups of attractors are approximated
+    let mut exact_params = params;
+    exact_params.theta = 0.;
+    let pos = Point2::new(1000., -20.);
+    let exact = attractors
+        .iter()
+        .fold(Vector2::zeros(), |acc, (attractor_pos, mass)| {
+            acc + params.pull(&pos, attractor_pos, *mass)
+        });
+    assert!((tree.acceleration_at(&pos, None, &exact_params) - exact).norm() < 1e-4);
+}
+
+#[test]
+fn heavy_objects_attract_each_other() {
+    use super::entities::ObjectShape;
+    use super::world::spawn_test_object;
+
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    world.set_gravitation(true);
+    let radius = (CONF.physics.gravitation.min_attractor_mass / 3.).sqrt();
+    let ids: Vec<_> = [-radius * 3., radius * 3.]
+        .iter()
+        .map(|&x| {
+            spawn_test_object(
+                &mut world,
+                ObjectShape::Circle { radius },
+                Vector2::new(x, 0.),
+            )
+        })
+        .collect();
+
+    for _ in 0..10 {
+        world.step();
+    }
+    let (left, _) = world.entity_state(&ids[0]).unwrap();
+    let (right, _) = world.entity_state(&ids[1]).unwrap();
+    assert!(left.velocity_x > 0. && right.velocity_x < 0.);
+    assert!((left.velocity_x + right.velocity_x).abs() < 1e-4);
+
+    world.set_gravitation(false);
+    world.step();
+    let (coasting, _) = world.entity_state(&ids[0]).unwrap();
+    assert!((coasting.velocity_x - left.velocity_x).abs() < 1e-4);
+}
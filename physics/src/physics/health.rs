@@ -0,0 +1,162 @@
+//! Implements player health: players are damaged by other players' beams and by hard collisions,
+//! die once their health runs out, and are spawned back into the world after a delay.
+
+use conf::CONF;
+
+use super::entities::{Entity, EntityHandles};
+use super::updates::WorldUpdate;
+use super::world::{key_to_uuid, EntityKey, PhysicsWorldInner};
+
+impl PhysicsWorldInner<()> {
+    /// Removes `damage` health from the player with the given ID, killing them if it runs out.
+    /// `attacker_id` is the player responsible for the damage, if any.
+    pub fn damage_player(
+        &mut self,
+        id: &EntityKey,
+        damage: f32,
+        attacker_id: Option<&EntityKey>,
+        updates: &mut Vec<WorldUpdate>,
+    ) {
+        let health = match self.uuid_map.get_mut(id) {
+            Some(EntityHandles {
+                entity: Entity::Player(player),
+                ..
+            }) => {
+                player.health = (player.health - damage).max(0.);
+                player.health
+            }
+            _ => {
+                println!("ERROR: Tried to damage entity {} but it isn't a player", id);
+                return;
+            }
+        };
+
+        updates.push(WorldUpdate::HealthChange {
+            id: id.clone(),
+            health,
+        });
+
+        if health <= 0. {
+            self.remove_entity(id);
+            self.respawn_timers
+                .insert(id.clone(), CONF.game.player_respawn_delay_ticks);
+            updates.push(WorldUpdate::PlayerDeath {
+                id: id.clone(),
+                killer_id: attacker_id.cloned(),
+            });
+        }
+    }
+
+    /// Damages every player that is currently hit by another player's beam
+    pub fn apply_player_beam_damage(&mut self, updates: &mut Vec<WorldUpdate>) {
+        let mut hits: Vec<(EntityKey, EntityKey)> = Vec::new();
+        for (attacker_id, hit) in &self.beam_hits {
+            let target_id = match hit.target_id {
+                Some(ref target_id) => target_id,
+                None => continue,
+            };
+            let is_player = match self.uuid_map.get(target_id) {
+                Some(EntityHandles {
+                    entity: Entity::Player(_),
+                    ..
+                }) => true,
+                _ => false,
+            };
+
+            if is_player && target_id != attacker_id {
+                hits.push((target_id.clone(), attacker_id.clone()));
+            }
+        }
+
+        for (target_id, attacker_id) in hits {
+            // The target may have already been killed by another player's beam this tick
+            if !self.uuid_map.contains_key(&target_id) {
+                continue;
+            }
+
+            self.damage_player(
+                &target_id,
+                CONF.game.beam_damage_per_tick,
+                Some(&attacker_id),
+                updates,
+            );
+        }
+    }
+
+    /// Damages all players that were struck hard enough during the last step, given the impacts
+    /// returned by `PhysicsWorldInner::impacts`.
+    pub fn damage_impacted_players(
+        &mut self,
+        impacts: &[(EntityKey, f32)],
+        updates: &mut Vec<WorldUpdate>,
+    ) {
+        let conf = &CONF.game.collision_damage;
+
+        for (id, velocity_change) in impacts {
+            if *velocity_change < conf.min_velocity_change {
+                continue;
+            }
+
+            match self.uuid_map.get(id) {
+                Some(EntityHandles {
+                    entity: Entity::Player(_),
+                    ..
+                }) => (),
+                _ => continue,
+            }
+
+            let damage = velocity_change * conf.damage_per_velocity_change;
+            self.damage_player(id, damage, None, updates);
+        }
+    }
+
+    /// Counts down the respawn timers of all dead players, spawning back in any whose timers
+    /// have run out.
+    pub fn respawn_players(&mut self) {
+        let mut respawned = Vec::new();
+        for (id, ticks_remaining) in self.respawn_timers.iter_mut() {
+            if *ticks_remaining <= 1 {
+                respawned.push(id.clone());
+            } else {
+                *ticks_remaining -= 1;
+            }
+        }
+
+        for id in respawned {
+            self.respawn_timers.remove(&id);
+            self.spawn_player(key_to_uuid(&id));
+        }
+    }
+}
+
+#[test]
+fn players_respawn_after_dying() {
+    use super::world::uuid_to_key;
+    use worldgen::{gen_uuid, rng_from_seed};
+
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    let player_id = gen_uuid(&mut rng_from_seed(0));
+    let player_key = uuid_to_key(player_id);
+    world.spawn_player(player_id);
+
+    let mut updates = Vec::new();
+    world.damage_player(&player_key, CONF.game.player_max_health, None, &mut updates);
+    assert!(!world.uuid_map.contains_key(&player_key));
+    match updates.as_slice() {
+        [WorldUpdate::HealthChange { health, .. }, WorldUpdate::PlayerDeath { id, killer_id }] => {
+            assert_eq!(*health, 0.);
+            assert_eq!(*id, player_key);
+            assert!(killer_id.is_none());
+        }
+        _ => panic!("Unexpected updates after killing player: {:?}", updates),
+    }
+
+    for _ in 0..CONF.game.player_respawn_delay_ticks {
+        assert!(!world.uuid_map.contains_key(&player_key));
+        world.respawn_players();
+    }
+    match world.uuid_map[&player_key].entity {
+        Entity::Player(ref player) => assert_eq!(player.health, CONF.game.player_max_health),
+        _ => panic!("Respawned entity wasn't a player"),
+    }
+}
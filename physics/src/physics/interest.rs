@@ -0,0 +1,182 @@
+//! Implements area-of-interest filtering.  Each player is only sent updates about the entities
+//! within `CONF.network.view_radius` of them; creation and deletion events are generated as
+//! entities come into and go out of their view.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use nalgebra::{Isometry2, Point2, Vector2};
+use ncollide2d::bounding_volume::AABB;
+use ncollide2d::query::PointQuery;
+use ncollide2d::world::CollisionGroups;
+
+use conf::CONF;
+
+use super::updates::WorldUpdate;
+use super::world::{EntityKey, PhysicsWorldInner};
+
+impl PhysicsWorldInner<()> {
+    /// Returns the IDs of all entities whose bounding boxes are within the view radius of `pos`.
+    /// Candidates are found using the broad phase, so this is only accurate after a step.
+    pub fn entities_in_view(&self, pos: &Point2<f32>) -> BTreeSet<EntityKey> {
+        let radius = CONF.network.view_radius;
+        let half_extents = Vector2::new(radius, radius);
+        let aabb = AABB::new(*pos - half_extents, *pos + half_extents);
+
+        self.world
+            .collision_world()
+            .interferences_with_aabb(&aabb, &CollisionGroups::new())
+            .filter(|object| {
+                let object_aabb = object.shape().aabb(object.position());
+                object_aabb.distance_to_point(&Isometry2::identity(), pos, true) <= radius
+            })
+            .filter_map(|object| self.handle_map.get(&object.handle()))
+            .cloned()
+            .collect()
+    }
+
+    fn player_position(&self, player_id: &EntityKey) -> Option<Point2<f32>> {
+        let body_handle = self.uuid_map.get(player_id)?.body_handle;
+        let body = self.world.rigid_body(body_handle)?;
+        Some(Point2::from_coordinates(body.position().translation.vector))
+    }
+
+    /// Splits the updates produced by a tick into the lists of updates that need to be sent to
+    /// each player, keyed by player ID.  The entities in view of each player are re-computed, and
+    /// creation and deletion events are added for those that came into or went out of view since
+    /// the last call.  Dead players keep seeing what was in view when they died.
+    pub fn recipient_updates(
+        &mut self,
+        updates: &[WorldUpdate],
+    ) -> BTreeMap<EntityKey, Vec<WorldUpdate>> {
+        // Forget about players that have left the game
+        let departed: Vec<EntityKey> = self
+            .interest_sets
+            .keys()
+            .filter(|id| {
+                !self.uuid_map.contains_key(*id) && !self.respawn_timers.contains_key(*id)
+            }).cloned()
+            .collect();
+        for player_id in departed {
+            self.interest_sets.remove(&player_id);
+        }
+
+        let mut recipient_updates = BTreeMap::new();
+        let player_ids: Vec<EntityKey> = self.interest_sets.keys().cloned().collect();
+        for player_id in player_ids {
+            let prev_view = self.interest_sets.remove(&player_id).unwrap();
+            let view = match self.player_position(&player_id) {
+                Some(pos) => self.entities_in_view(&pos),
+                None => prev_view
+                    .iter()
+                    .filter(|id| self.uuid_map.contains_key(*id))
+                    .cloned()
+                    .collect(),
+            };
+
+            let mut player_updates: Vec<WorldUpdate> = view
+                .difference(&prev_view)
+                .map(|id| WorldUpdate::EntityCreated { id: id.clone() })
+                .collect();
+            player_updates.extend(
+                updates
+                    .iter()
+                    .filter(|update| match update {
+                        // These are covered by the changes to the set of entities in view
+                        WorldUpdate::EntityCreated { .. } | WorldUpdate::EntityDeleted { .. } => {
+                            false
+                        }
+                        // Players can see each other's names once they come into view
+                        WorldUpdate::Username { .. } => true,
+                        update => {
+                            let id = update.entity_id();
+                            *id == player_id || view.contains(id) || prev_view.contains(id)
+                        }
+                    }).cloned(),
+            );
+            // Deletions come last so that updates about entities that just went out of view (like
+            // the death of a player) are applied before they're removed.
+            player_updates.extend(
+                prev_view
+                    .difference(&view)
+                    .map(|id| WorldUpdate::EntityDeleted { id: id.clone() }),
+            );
+
+            self.interest_sets.insert(player_id.clone(), view);
+            recipient_updates.insert(player_id, player_updates);
+        }
+
+        recipient_updates
+    }
+}
+
+#[test]
+fn entities_entering_and_leaving_view_are_created_and_deleted() {
+    use nphysics2d::algebra::Velocity2;
+
+    use super::entities::ObjectShape;
+    use super::world::{spawn_test_object, uuid_to_key};
+
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    let corner = |x: f32, y: f32| Isometry2::new(Vector2::new(x, y), 0.);
+
+    let square = vec![
+        Point2::new(10., 10.),
+        Point2::new(-10., 10.),
+        Point2::new(-10., -10.),
+        Point2::new(10., -10.),
+    ];
+    let asteroid_id = spawn_test_object(
+        &mut world,
+        ObjectShape::Polygon(square),
+        Vector2::new(CONF.game.world_max_x, CONF.game.world_max_y),
+    );
+    let player_uuid = world.gen_uuid();
+    let player_id = uuid_to_key(player_uuid);
+    // Joining players are only told about the entities around them
+    world.spawn_player(player_uuid);
+    let spawn_pos = world.player_position(&player_id).unwrap();
+    assert_eq!(
+        world.interest_sets[&player_id].contains(&asteroid_id),
+        world.entities_in_view(&spawn_pos).contains(&asteroid_id)
+    );
+
+    let is_deletion = |update: &WorldUpdate| match update {
+        WorldUpdate::EntityDeleted { id } => *id == asteroid_id,
+        _ => false,
+    };
+    let is_creation = |update: &WorldUpdate| match update {
+        WorldUpdate::EntityCreated { id } => *id == asteroid_id,
+        _ => false,
+    };
+
+    // Bring the asteroid into view, and then move away from it
+    world.update_movement(
+        &player_id,
+        &corner(CONF.game.world_max_x - 100., CONF.game.world_max_y),
+        &Velocity2::zero(),
+        None,
+    );
+    let updates = world.tick(Vec::new(), true);
+    world.recipient_updates(&updates);
+    assert!(world.interest_sets[&player_id].contains(&asteroid_id));
+
+    world.update_movement(
+        &player_id,
+        &corner(CONF.game.world_min_x, CONF.game.world_min_y),
+        &Velocity2::zero(),
+        None,
+    );
+    let updates = world.tick(Vec::new(), true);
+    let recipient_updates = world.recipient_updates(&updates);
+    assert!(recipient_updates[&player_id].iter().any(is_deletion));
+
+    world.update_movement(
+        &asteroid_id,
+        &corner(CONF.game.world_min_x + 100., CONF.game.world_min_y),
+        &Velocity2::zero(),
+        None,
+    );
+    let updates = world.tick(Vec::new(), true);
+    let recipient_updates = world.recipient_updates(&updates);
+    assert!(recipient_updates[&player_id].iter().any(is_creation));
+}
@@ -0,0 +1,174 @@
+//! Implements lag compensation for players' beams.  Clients render the world a few ticks behind
+//! the newest state that they've received, so what a player sees under their beam lags behind the
+//! server's current state by their latency plus `CONF.network.render_delay_ticks`.
+//!
+//! To make hits fair, the world keeps the isometries of all entities for the last
+//! `CONF.network.max_lag_compensation_ticks` ticks.  Each player reports the tick that they were
+//! rendering along with their inputs, and their beam is evaluated against the entities as they
+//! were at that tick.  Only the targets are rewound; the beam is cast from the player's current
+//! position since clients move their own player without waiting for the server.
+
+use std::collections::BTreeMap;
+use std::iter;
+
+use nalgebra::{Isometry2, Point2, Translation2, Vector2};
+
+use conf::CONF;
+
+use super::beams::{beam_hit, BeamHit};
+use super::world::{EntityKey, PhysicsWorldInner};
+use super::wrap::ghost_offsets;
+
+/// The world-space isometries of the colliders of all entities at the end of some tick
+pub struct IsometrySnapshot {
+    pub tick: u32,
+    pub isometries: BTreeMap<EntityKey, Isometry2<f32>>,
+}
+
+impl<T> PhysicsWorldInner<T> {
+    /// Stores the isometries of all entities as of the end of the current tick, dropping the
+    /// oldest ones once there are more than `CONF.network.max_lag_compensation_ticks` of them.
+    pub fn record_isometries(&mut self) {
+        let isometries = self
+            .uuid_map
+            .iter()
+            .filter_map(|(id, handles)| {
+                let collider = self.world.collider(handles.collider_handle)?;
+                Some((id.clone(), *collider.position()))
+            }).collect();
+        self.isometry_history.push_back(IsometrySnapshot {
+            tick: self.tick_count,
+            isometries,
+        });
+
+        while self.isometry_history.len() > CONF.network.max_lag_compensation_ticks as usize {
+            self.isometry_history.pop_front();
+        }
+    }
+
+    /// Returns the snapshot to evaluate the beam of a player rendering `render_tick` against, or
+    /// `None` if they're seeing the current state of the world.  Ticks older than the oldest
+    /// snapshot are clamped to it so that laggy players can't reach arbitrarily far back.
+    fn snapshot_for(&self, render_tick: u32) -> Option<&IsometrySnapshot> {
+        let newest = self.isometry_history.back()?;
+        if render_tick >= newest.tick {
+            return None;
+        }
+
+        self.isometry_history
+            .iter()
+            .find(|snapshot| snapshot.tick >= render_tick)
+    }
+
+    /// Casts the beam of the given player against the entities as they were at `render_tick`.
+    /// Entities that have been removed since can't be hit, and ones that have been spawned since
+    /// weren't visible to the player and so can't be hit either.  Falls back to `cast_beam` if
+    /// the player is seeing the current state of the world.
+    pub fn cast_beam_at(&self, player_id: &EntityKey, render_tick: u32) -> Option<BeamHit> {
+        let snapshot = match self.snapshot_for(render_tick) {
+            Some(snapshot) => snapshot,
+            None => return self.cast_beam(player_id),
+        };
+        let ray = self.beam_ray(player_id)?;
+        let beam_length = CONF.game.player_beam_length;
+
+        let mut nearest_hit: Option<(&EntityKey, f32)> = None;
+        for (target_id, isometry) in &snapshot.isometries {
+            if target_id == player_id {
+                continue;
+            }
+            let collider = match self
+                .uuid_map
+                .get(target_id)
+                .and_then(|handles| self.world.collider(handles.collider_handle))
+            {
+                Some(collider) => collider,
+                None => continue,
+            };
+            let ray_cast = match collider.shape().as_ray_cast() {
+                Some(ray_cast) => ray_cast,
+                None => continue,
+            };
+
+            // The ghost colliders that existed at the time aren't kept, so targets near the edges
+            // of wrapping worlds are also tested at the positions their ghosts would have had
+            let offsets = if CONF.game.wrap_world {
+                let center = Point2::from_coordinates(isometry.translation.vector);
+                ghost_offsets(&center, CONF.game.wrap_ghost_margin)
+            } else {
+                Vec::new()
+            };
+            for offset in iter::once(Vector2::zeros()).chain(offsets) {
+                let pos = Translation2::from_vector(offset) * *isometry;
+                let toi = match ray_cast.toi_with_ray(&pos, &ray, true) {
+                    Some(toi) if toi <= beam_length => toi,
+                    _ => continue,
+                };
+
+                match nearest_hit {
+                    Some((_, nearest_toi)) if nearest_toi <= toi => (),
+                    _ => nearest_hit = Some((target_id, toi)),
+                }
+            }
+        }
+
+        Some(beam_hit(
+            &ray,
+            nearest_hit.map(|(target_id, toi)| (target_id.clone(), toi)),
+        ))
+    }
+}
+
+#[test]
+fn beams_are_evaluated_against_the_tick_players_were_rendering() {
+    use nphysics2d::algebra::Velocity2;
+
+    use super::entities::{Entity, EntityHandles, ObjectShape};
+    use super::world::{spawn_test_object, uuid_to_key};
+
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    let vertices = vec![
+        Point2::new(-5., -5.),
+        Point2::new(5., -5.),
+        Point2::new(5., 5.),
+        Point2::new(-5., 5.),
+    ];
+    let asteroid_pos = Vector2::new(30., 0.);
+    let asteroid_id = spawn_test_object(&mut world, ObjectShape::Polygon(vertices), asteroid_pos);
+    // Moves straight down, out of the path of a beam aimed to the right
+    world.update_movement(
+        &asteroid_id,
+        &Isometry2::new(asteroid_pos, 0.),
+        &Velocity2::new(Vector2::new(0., 1.), 0.),
+        None,
+    );
+
+    let player_uuid = world.gen_uuid();
+    let player_id = uuid_to_key(player_uuid);
+    world.spawn_player(player_uuid);
+    world.update_movement(
+        &player_id,
+        &Isometry2::identity(),
+        &Velocity2::zero(),
+        None,
+    );
+    if let Some(EntityHandles {
+        entity: Entity::Player(player),
+        ..
+    }) = world.uuid_map.get_mut(&player_id)
+    {
+        player.beam_aim = Point2::new(1000., 0.);
+    }
+
+    let start_tick = world.tick_count;
+    for _ in 0..20 {
+        world.tick(Vec::new(), false);
+    }
+
+    // The asteroid has moved out of the beam's path by now but was in it at the start
+    assert_eq!(world.cast_beam(&player_id).unwrap().target_id, None);
+    let rewound_hit = world.cast_beam_at(&player_id, start_tick).unwrap();
+    assert_eq!(rewound_hit.target_id, Some(asteroid_id));
+    let cur_tick = world.tick_count;
+    assert_eq!(world.cast_beam_at(&player_id, cur_tick), world.cast_beam(&player_id));
+}
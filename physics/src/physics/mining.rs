@@ -0,0 +1,141 @@
+//! Implements mining: every tick that a player's beam hits a mineable object such as an asteroid,
+//! material is removed from it.  Depleted objects break apart into smaller, weakened fragments if
+//! they're fracturable and are removed from the world otherwise.
+
+use conf::{Behaviour, CONF};
+
+use super::entities::{Entity, EntityHandles};
+use super::updates::WorldUpdate;
+use super::world::{EntityKey, PhysicsWorldInner};
+
+impl PhysicsWorldInner<()> {
+    /// Damages every mineable object that is currently hit by a player's beam, destroying any
+    /// that have run out of health and pushing an `ObjectDepleted` update for each of them.
+    pub fn apply_beam_damage(&mut self, updates: &mut Vec<WorldUpdate>) {
+        let mut depleted: Vec<(EntityKey, EntityKey)> = Vec::new();
+
+        for (player_id, hit) in &self.beam_hits {
+            let target_id = match hit.target_id {
+                Some(ref target_id) => target_id,
+                None => continue,
+            };
+            let object = match self.uuid_map.get_mut(target_id) {
+                Some(EntityHandles {
+                    entity: Entity::Object(object),
+                    ..
+                }) if object.archetype.has_behaviour(Behaviour::Mineable) => object,
+                _ => continue,
+            };
+
+            // Already depleted by another player's beam this tick
+            if object.health <= 0. {
+                continue;
+            }
+
+            object.health -= CONF.game.beam_damage_per_tick;
+            if object.health <= 0. {
+                depleted.push((target_id.clone(), player_id.clone()));
+            }
+        }
+
+        for (id, miner_id) in depleted {
+            let fracturable = match self.uuid_map.get(&id) {
+                Some(handles) => handles.entity.archetype().has_behaviour(Behaviour::Fracturable),
+                None => continue,
+            };
+            if fracturable {
+                self.fracture_object(&id, CONF.game.asteroid_fracture.depleted_health_ratio);
+            } else {
+                self.remove_entity(&id);
+            }
+            updates.push(WorldUpdate::ObjectDepleted { id, miner_id });
+        }
+    }
+}
+
+#[test]
+fn beamed_asteroids_are_depleted_and_removed() {
+    use nalgebra::{Point2, Vector2};
+
+    use super::beams::BeamHit;
+    use super::entities::ObjectShape;
+    use super::world::{spawn_test_object, uuid_to_key};
+
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    // Small enough that it doesn't leave behind any fragments
+    let vertices = vec![
+        Point2::new(-1., -1.),
+        Point2::new(1., -1.),
+        Point2::new(1., 1.),
+        Point2::new(-1., 1.),
+    ];
+    let asteroid_key =
+        spawn_test_object(&mut world, ObjectShape::Polygon(vertices), Vector2::zeros());
+    let player_key = uuid_to_key(world.gen_uuid());
+    world.beam_hits.insert(
+        player_key.clone(),
+        BeamHit {
+            target_id: Some(asteroid_key.clone()),
+            point: Point2::origin(),
+        },
+    );
+
+    let mut updates = Vec::new();
+    while world.uuid_map.contains_key(&asteroid_key) {
+        assert!(updates.is_empty());
+        world.apply_beam_damage(&mut updates);
+    }
+
+    match updates.as_slice() {
+        [WorldUpdate::ObjectDepleted { id, miner_id }] => {
+            assert_eq!(*id, asteroid_key);
+            assert_eq!(*miner_id, player_key);
+        }
+        _ => panic!("Expected a single depletion update; got {:?}", updates),
+    }
+    assert!(world.beam_hits[&player_key].target_id.is_none());
+}
+
+#[test]
+fn mining_fracturable_asteroids_eventually_consumes_them() {
+    use nalgebra::{Point2, Vector2};
+
+    use super::beams::BeamHit;
+    use super::entities::{builtin_archetype, ObjectEntity, ObjectShape};
+    use super::world::{spawn_test_object, uuid_to_key};
+
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    // Large enough to break into several generations of fragments
+    let shape = ObjectShape::Polygon(vec![
+        Point2::new(-20., -20.),
+        Point2::new(20., -20.),
+        Point2::new(20., 20.),
+        Point2::new(-20., 20.),
+    ]);
+    let initial_health = ObjectEntity::new(builtin_archetype("asteroid"), shape.clone()).health;
+    spawn_test_object(&mut world, shape, Vector2::zeros());
+    let player_key = uuid_to_key(world.gen_uuid());
+
+    let mut updates = Vec::new();
+    let mut ticks = 0;
+    while let Some(target_id) = world.uuid_map.keys().next().cloned() {
+        world.beam_hits.insert(
+            player_key.clone(),
+            BeamHit {
+                target_id: Some(target_id),
+                point: Point2::origin(),
+            },
+        );
+        world.apply_beam_damage(&mut updates);
+        ticks += 1;
+        assert!(ticks < 100_000, "Mining never consumed the asteroid");
+    }
+
+    // Every generation of fragments has a fraction of the health of the one before it, and each
+    // object takes at most one tick of damage more than its health
+    let ratio = CONF.game.asteroid_fracture.depleted_health_ratio;
+    let max_ticks =
+        initial_health / (1. - ratio) / CONF.game.beam_damage_per_tick + updates.len() as f32;
+    assert!(updates.len() > 1);
+    assert!((ticks as f32) <= max_ticks);
+}
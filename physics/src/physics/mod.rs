@@ -3,10 +3,25 @@
 
 use nalgebra::Vector2;
 
+pub mod beams;
+pub mod encoding;
 pub mod entities;
+pub mod force_fields;
+pub mod fracture;
+pub mod gravitation;
+pub mod health;
+pub mod interest;
+pub mod lag_compensation;
+pub mod mining;
+pub mod persistence;
+pub mod recording;
 #[cfg(feature = "elixir-interop")]
 pub mod server;
+pub mod spawn;
+pub mod updates;
+pub mod user_diff;
 pub mod world;
+pub mod wrap;
 
 pub use self::world::PhysicsWorldInner;
 
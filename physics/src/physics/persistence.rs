@@ -0,0 +1,491 @@
+//! Saves the state of every entity in a world to a file and loads it back so that rooms can
+//! survive server restarts and be captured for debugging.
+//!
+//! Saves hold the world's force fields, whether gravitation is enabled, and the state of its RNG
+//! along with each entity's ID, data, isometry, velocity, body status, and material.  The RNG is
+//! saved so that entities spawned after loading don't reuse the IDs of ones that already exist.
+//! Things that are derived from the entities (beam hits, ghost colliders, interest sets) are
+//! rebuilt when the save is loaded, and dead players waiting to respawn aren't saved.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use nalgebra::{Isometry2, Point2, Vector2};
+use nphysics2d::algebra::Velocity2;
+use nphysics2d::object::{Body, BodyStatus};
+
+use super::encoding::{
+    read_f32, read_u16, read_u32, read_u64, read_u8, read_uuid, write_f32, write_u16, write_u32,
+    write_u64, write_uuid, MOVEMENTS,
+};
+use super::entities::{
+    archetype, Entity, EntityMaterial, EntitySpawn, ObjectEntity, ObjectShape, PlayerEntity,
+};
+use super::force_fields::{ForceField, ForceFieldKind};
+use super::world::{key_to_uuid, PhysicsWorldInner};
+use worldgen::restore_rng;
+
+const MAGIC: &[u8; 4] = b"IOWS";
+/// Bumped whenever the format changes.  Saves made with other versions can't be loaded.
+pub const SAVE_VERSION: u16 = 7;
+
+const PLAYER_TAG: u8 = 0;
+/// Objects are saved along with the name of their archetype
+const OBJECT_TAG: u8 = 1;
+
+const POLYGON_TAG: u8 = 0;
+const CIRCLE_TAG: u8 = 1;
+const CAPSULE_TAG: u8 = 2;
+
+const GRAVITY_WELL_TAG: u8 = 0;
+const REPULSOR_TAG: u8 = 1;
+const CURRENT_TAG: u8 = 2;
+
+/// Body statuses are stored as their index in this array
+const BODY_STATUSES: [BodyStatus; 4] = [
+    BodyStatus::Dynamic,
+    BodyStatus::Static,
+    BodyStatus::Kinematic,
+    BodyStatus::Disabled,
+];
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file isn't a saved world or one of its entities is invalid
+    Malformed,
+    /// The save contains an object whose archetype is no longer defined in the config
+    UnknownArchetype(String),
+    UnsupportedVersion(u16),
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+fn write_points<W: Write>(writer: &mut W, points: &[Point2<f32>]) -> io::Result<()> {
+    write_u32(writer, points.len() as u32)?;
+    for pt in points {
+        write_f32(writer, pt.x)?;
+        write_f32(writer, pt.y)?;
+    }
+    Ok(())
+}
+
+fn read_points<R: Read>(reader: &mut R) -> io::Result<Vec<Point2<f32>>> {
+    let count = read_u32(reader)?;
+    (0..count)
+        .map(|_| Ok(Point2::new(read_f32(reader)?, read_f32(reader)?)))
+        .collect()
+}
+
+fn write_name<W: Write>(writer: &mut W, name: &str) -> io::Result<()> {
+    write_u16(writer, name.len() as u16)?;
+    writer.write_all(name.as_bytes())
+}
+
+fn read_name<R: Read>(reader: &mut R) -> Result<String, LoadError> {
+    let mut bytes = vec![0u8; read_u16(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| LoadError::Malformed)
+}
+
+fn write_shape<W: Write>(writer: &mut W, shape: &ObjectShape) -> io::Result<()> {
+    match shape {
+        ObjectShape::Polygon(vertices) => {
+            writer.write_all(&[POLYGON_TAG])?;
+            write_points(writer, vertices)
+        }
+        ObjectShape::Circle { radius } => {
+            writer.write_all(&[CIRCLE_TAG])?;
+            write_f32(writer, *radius)
+        }
+        ObjectShape::Capsule {
+            half_height,
+            radius,
+        } => {
+            writer.write_all(&[CAPSULE_TAG])?;
+            write_f32(writer, *half_height)?;
+            write_f32(writer, *radius)
+        }
+    }
+}
+
+fn read_shape<R: Read>(reader: &mut R) -> Result<ObjectShape, LoadError> {
+    let shape = match read_u8(reader)? {
+        POLYGON_TAG => ObjectShape::Polygon(read_points(reader)?),
+        CIRCLE_TAG => ObjectShape::Circle {
+            radius: read_f32(reader)?,
+        },
+        CAPSULE_TAG => ObjectShape::Capsule {
+            half_height: read_f32(reader)?,
+            radius: read_f32(reader)?,
+        },
+        _ => return Err(LoadError::Malformed),
+    };
+
+    Ok(shape)
+}
+
+fn write_force_field<W: Write>(writer: &mut W, field: &ForceField) -> io::Result<()> {
+    let (tag, vals) = match field.kind {
+        ForceFieldKind::GravityWell { strength } => (GRAVITY_WELL_TAG, [strength, 0.]),
+        ForceFieldKind::Repulsor { strength } => (REPULSOR_TAG, [strength, 0.]),
+        ForceFieldKind::Current { acceleration } => (CURRENT_TAG, [acceleration.x, acceleration.y]),
+    };
+
+    writer.write_all(&[tag])?;
+    for val in &[field.center.x, field.center.y, field.radius, vals[0], vals[1]] {
+        write_f32(writer, *val)?;
+    }
+    Ok(())
+}
+
+fn read_force_field<R: Read>(reader: &mut R) -> Result<ForceField, LoadError> {
+    let tag = read_u8(reader)?;
+    let mut vals = [0.; 5];
+    for val in &mut vals {
+        *val = read_f32(reader)?;
+    }
+
+    let kind = match tag {
+        GRAVITY_WELL_TAG => ForceFieldKind::GravityWell { strength: vals[3] },
+        REPULSOR_TAG => ForceFieldKind::Repulsor { strength: vals[3] },
+        CURRENT_TAG => ForceFieldKind::Current {
+            acceleration: Vector2::new(vals[3], vals[4]),
+        },
+        _ => return Err(LoadError::Malformed),
+    };
+
+    Ok(ForceField {
+        center: Point2::new(vals[0], vals[1]),
+        radius: vals[2],
+        kind,
+    })
+}
+
+fn write_entity<W: Write>(writer: &mut W, entity: &Entity) -> io::Result<()> {
+    match entity {
+        Entity::Player(PlayerEntity {
+            size,
+            movement,
+            beam_aim,
+            beam_on,
+            health,
+        }) => {
+            let movement_ix = MOVEMENTS.iter().position(|m| m == movement).unwrap();
+            writer.write_all(&[PLAYER_TAG])?;
+            write_u32(writer, *size)?;
+            writer.write_all(&[movement_ix as u8])?;
+            write_f32(writer, beam_aim.x)?;
+            write_f32(writer, beam_aim.y)?;
+            writer.write_all(&[*beam_on as u8])?;
+            write_f32(writer, *health)
+        }
+        Entity::Object(ObjectEntity {
+            archetype,
+            shape,
+            health,
+        }) => {
+            writer.write_all(&[OBJECT_TAG])?;
+            write_name(writer, archetype.name)?;
+            write_shape(writer, shape)?;
+            write_f32(writer, *health)
+        }
+    }
+}
+
+fn read_entity<R: Read>(reader: &mut R) -> Result<Entity, LoadError> {
+    let entity = match read_u8(reader)? {
+        PLAYER_TAG => {
+            let size = read_u32(reader)?;
+            let movement_ix = read_u8(reader)? as usize;
+            let movement = *MOVEMENTS.get(movement_ix).ok_or(LoadError::Malformed)?;
+            let beam_aim = Point2::new(read_f32(reader)?, read_f32(reader)?);
+            let beam_on = read_u8(reader)? != 0;
+            let health = read_f32(reader)?;
+
+            Entity::Player(PlayerEntity {
+                size,
+                movement,
+                beam_aim,
+                beam_on,
+                health,
+            })
+        }
+        OBJECT_TAG => {
+            let name = read_name(reader)?;
+            let archetype = match archetype(&name) {
+                Some(archetype) => archetype,
+                None => return Err(LoadError::UnknownArchetype(name)),
+            };
+
+            Entity::Object(ObjectEntity {
+                archetype,
+                shape: read_shape(reader)?,
+                health: read_f32(reader)?,
+            })
+        }
+        _ => return Err(LoadError::Malformed),
+    };
+
+    Ok(entity)
+}
+
+impl PhysicsWorldInner<()> {
+    /// Saves the state of all entities in the world to `path`.  The save is written to a
+    /// temporary file first so that an existing save isn't corrupted if writing it fails.
+    pub fn save_state<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            writer.write_all(MAGIC)?;
+            write_u16(&mut writer, SAVE_VERSION)?;
+            write_u32(&mut writer, self.tick_count)?;
+            writer.write_all(&[self.gravitation_enabled as u8])?;
+            write_u64(&mut writer, self.rng_seed)?;
+            write_u64(&mut writer, self.rng.get_word_pos() as u64)?;
+            write_u32(&mut writer, self.force_fields.len() as u32)?;
+            for field in &self.force_fields {
+                write_force_field(&mut writer, field)?;
+            }
+            write_u32(&mut writer, self.uuid_map.len() as u32)?;
+
+            for (id, handles) in &self.uuid_map {
+                let (movement, _) = self
+                    .entity_state(id)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Entity not in world"))?;
+                let body_status = match self.world.body(handles.body_handle) {
+                    Body::RigidBody(rigid_body) => rigid_body.status(),
+                    _ => BodyStatus::Static,
+                };
+                let status_ix = BODY_STATUSES
+                    .iter()
+                    .position(|status| *status == body_status)
+                    .unwrap();
+
+                write_uuid(&mut writer, &key_to_uuid(id))?;
+                writer.write_all(&[status_ix as u8])?;
+                let material = &handles.material;
+                for val in &[
+                    movement.pos_x,
+                    movement.pos_y,
+                    movement.rotation,
+                    movement.velocity_x,
+                    movement.velocity_y,
+                    movement.angular_velocity,
+                    material.restitution,
+                    material.friction,
+                    material.density,
+                ] {
+                    write_f32(&mut writer, *val)?;
+                }
+                write_entity(&mut writer, &handles.entity)?;
+            }
+
+            writer.flush()?;
+        }
+
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Replaces all entities in the world with the ones saved in the file at `path`.  Players
+    /// are only restored if `restore_players` is set, since restored players have no clients
+    /// controlling them.  The world is left untouched if the save can't be loaded.
+    pub fn load_state<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        restore_players: bool,
+    ) -> Result<(), LoadError> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(LoadError::Malformed);
+        }
+        let version = read_u16(&mut reader)?;
+        if version != SAVE_VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+        let tick_count = read_u32(&mut reader)?;
+        let gravitation_enabled = read_u8(&mut reader)? != 0;
+        let rng_seed = read_u64(&mut reader)?;
+        let rng_word_pos = read_u64(&mut reader)?;
+        let force_field_count = read_u32(&mut reader)?;
+        let force_fields = (0..force_field_count)
+            .map(|_| read_force_field(&mut reader))
+            .collect::<Result<Vec<_>, _>>()?;
+        let entity_count = read_u32(&mut reader)?;
+
+        let mut entity_spawns = Vec::with_capacity(entity_count as usize);
+        for _ in 0..entity_count {
+            let uuid = read_uuid(&mut reader)?;
+            let status_ix = read_u8(&mut reader)? as usize;
+            let body_status = *BODY_STATUSES.get(status_ix).ok_or(LoadError::Malformed)?;
+            let mut vals = [0.; 9];
+            for val in &mut vals {
+                *val = read_f32(&mut reader)?;
+            }
+            let entity = read_entity(&mut reader)?;
+
+            entity_spawns.push((
+                uuid,
+                EntitySpawn {
+                    isometry: Isometry2::new(Vector2::new(vals[0], vals[1]), vals[2]),
+                    velocity: Velocity2::new(Vector2::new(vals[3], vals[4]), vals[5]),
+                    entity,
+                    data: (),
+                    body_status,
+                    material: Some(EntityMaterial {
+                        restitution: vals[6],
+                        friction: vals[7],
+                        density: vals[8],
+                    }),
+                },
+            ));
+        }
+
+        self.clear();
+        self.tick_count = tick_count;
+        self.force_fields = force_fields;
+        self.gravitation_enabled = gravitation_enabled;
+        self.rng = restore_rng(rng_seed, rng_word_pos);
+        self.rng_seed = rng_seed;
+        for (uuid, entity_spawn) in entity_spawns {
+            if let Entity::Player(_) = entity_spawn.entity {
+                if !restore_players {
+                    continue;
+                }
+            }
+
+            self.spawn_entity(uuid, entity_spawn);
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn saved_worlds_are_restored() {
+    use std::env;
+
+    use super::world::{spawn_test_object, uuid_to_key};
+
+    let path = env::temp_dir().join("io-game-save-test.world");
+
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    world.initialize(42);
+    let player_id = uuid_to_key(world.gen_uuid());
+    world.spawn_player(key_to_uuid(&player_id));
+    world.set_gravitation(true);
+    let round_shapes = [
+        ObjectShape::Circle { radius: 4. },
+        ObjectShape::Capsule {
+            half_height: 6.,
+            radius: 2.,
+        },
+    ];
+    for (i, shape) in round_shapes.iter().enumerate() {
+        spawn_test_object(&mut world, shape.clone(), Vector2::new(i as f32 * 20., 0.));
+    }
+    for _ in 0..30 {
+        world.tick(Vec::new(), false);
+    }
+    world.save_state(&path).unwrap();
+
+    let mut restored: PhysicsWorldInner = PhysicsWorldInner::new();
+    restored.load_state(&path, true).unwrap();
+    assert_eq!(restored.tick_count, world.tick_count);
+    assert_eq!(restored.force_fields, world.force_fields);
+    assert!(restored.gravitation_enabled);
+    assert_eq!(
+        restored.uuid_map.keys().collect::<Vec<_>>(),
+        world.uuid_map.keys().collect::<Vec<_>>()
+    );
+    for id in world.uuid_map.keys() {
+        let (expected, _) = world.entity_state(id).unwrap();
+        let (actual, _) = restored.entity_state(id).unwrap();
+        assert!(!actual.has_drifted_from(&expected));
+        assert_eq!(restored.uuid_map[id].material, world.uuid_map[id].material);
+        if let Entity::Object(ObjectEntity { shape, .. }) = &world.uuid_map[id].entity {
+            match &restored.uuid_map[id].entity {
+                Entity::Object(restored_object) => assert_eq!(restored_object.shape, *shape),
+                entity => panic!("Object was restored as {:?}", entity),
+            }
+        }
+    }
+
+    restored.load_state(&path, false).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(!restored.uuid_map.contains_key(&player_id));
+    assert_eq!(restored.uuid_map.len(), world.uuid_map.len() - 1);
+}
+
+#[test]
+fn reloaded_worlds_dont_reuse_entity_ids() {
+    use std::collections::BTreeSet;
+    use std::env;
+
+    use conf::Behaviour;
+
+    use super::world::EntityKey;
+
+    let path = env::temp_dir().join("io-game-reload-test.world");
+
+    // Fractures the largest fracturable object in the world, returning the IDs of its fragments
+    let fracture_largest = |world: &mut PhysicsWorldInner| -> Vec<EntityKey> {
+        let existing: BTreeSet<EntityKey> = world.uuid_map.keys().cloned().collect();
+        let (target_id, _) = world
+            .uuid_map
+            .iter()
+            .filter_map(|(id, handles)| match &handles.entity {
+                Entity::Object(object)
+                    if object.archetype.has_behaviour(Behaviour::Fracturable) =>
+                {
+                    Some((id.clone(), object.max_health()))
+                }
+                _ => None,
+            }).max_by(|(_, health_1), (_, health_2)| health_1.partial_cmp(health_2).unwrap())
+            .unwrap();
+        world.fracture_object(&target_id, 1.0);
+
+        world
+            .uuid_map
+            .keys()
+            .filter(|id| !existing.contains(*id))
+            .cloned()
+            .collect()
+    };
+
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    world.initialize(42);
+    world.save_state(&path).unwrap();
+
+    let mut loaded: PhysicsWorldInner = PhysicsWorldInner::new();
+    loaded.load_state(&path, false).unwrap();
+    let first_fragments = fracture_largest(&mut loaded);
+    loaded.save_state(&path).unwrap();
+
+    let mut reloaded: PhysicsWorldInner = PhysicsWorldInner::new();
+    reloaded.load_state(&path, false).unwrap();
+    fs::remove_file(&path).unwrap();
+    let entity_count = reloaded.uuid_map.len();
+    let second_fragments = fracture_largest(&mut reloaded);
+
+    assert!(!first_fragments.is_empty());
+    assert!(!second_fragments.is_empty());
+    assert_eq!(
+        reloaded.uuid_map.len(),
+        entity_count - 1 + second_fragments.len()
+    );
+    for id in &first_fragments {
+        assert!(reloaded.uuid_map.contains_key(id));
+        assert!(!second_fragments.contains(id));
+    }
+}
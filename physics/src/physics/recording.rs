@@ -0,0 +1,394 @@
+//! Records every input applied to a world so that sessions can be replayed deterministically when
+//! tracking down bugs.
+//!
+//! A recording consists of a header containing the seed that the world was generated from followed
+//! by a log of entries: players that joined or left and gravitation being toggled in between ticks,
+//! and the diffs applied during each tick along with a checksum of the states of all entities after
+//! it.  Since world generation and all randomness within the world are derived from the seed,
+//! replaying rebuilds the world from it, re-applies the log, and checks that every tick ends with
+//! the same checksum.  This means that nothing other than the recorded events may draw from the
+//! world's RNG while it's being recorded, since the replay wouldn't draw the same values.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+
+use conf::CONF;
+
+use super::encoding::{
+    read_f32, read_u16, read_u32, read_u64, read_u8, read_uuid, write_f32, write_u16, write_u32,
+    write_u64, write_uuid, MOVEMENTS,
+};
+use super::user_diff::{InternalUserDiff, InternalUserDiffAction};
+use super::world::{key_to_uuid, uuid_to_key, PhysicsWorldInner};
+
+const MAGIC: &[u8; 4] = b"IORC";
+/// Bumped whenever the format changes.  Recordings made with other versions can't be replayed.
+pub const RECORDING_VERSION: u16 = 3;
+
+const SPAWN_TAG: u8 = 0;
+const DESPAWN_TAG: u8 = 1;
+const TICK_TAG: u8 = 2;
+const GRAVITATION_TAG: u8 = 3;
+
+const MOVEMENT_TAG: u8 = 0;
+const BEAM_AIM_TAG: u8 = 1;
+const BEAM_TOGGLE_TAG: u8 = 2;
+const USERNAME_TAG: u8 = 3;
+
+/// Writes the inputs applied to a world to a recording file
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    /// Creates a new recording at `path` for a world generated from `seed`
+    pub fn create<P: AsRef<Path>>(path: P, seed: u64) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        write_u16(&mut writer, RECORDING_VERSION)?;
+        write_u64(&mut writer, seed)?;
+
+        Ok(Recorder { writer })
+    }
+
+    pub fn record_spawn(&mut self, player_id: &Uuid) -> io::Result<()> {
+        self.writer.write_all(&[SPAWN_TAG])?;
+        write_uuid(&mut self.writer, player_id)
+    }
+
+    pub fn record_despawn(&mut self, entity_id: &Uuid) -> io::Result<()> {
+        self.writer.write_all(&[DESPAWN_TAG])?;
+        write_uuid(&mut self.writer, entity_id)
+    }
+
+    pub fn record_gravitation(&mut self, enabled: bool) -> io::Result<()> {
+        self.writer.write_all(&[GRAVITATION_TAG, enabled as u8])
+    }
+
+    /// Records the start of a tick along with all of the diffs applied during it.  Must be
+    /// followed by a call to `record_checksum` once the tick has completed.
+    pub fn record_tick(&mut self, update_all: bool, diffs: &[InternalUserDiff]) -> io::Result<()> {
+        self.writer.write_all(&[TICK_TAG, update_all as u8])?;
+        write_u32(&mut self.writer, diffs.len() as u32)?;
+
+        for InternalUserDiff {
+            id,
+            action,
+            render_tick,
+        } in diffs
+        {
+            write_uuid(&mut self.writer, &key_to_uuid(id))?;
+            self.writer.write_all(&[render_tick.is_some() as u8])?;
+            write_u32(&mut self.writer, render_tick.unwrap_or(0))?;
+            match action {
+                InternalUserDiffAction::Movement(movement) => {
+                    let movement_ix = MOVEMENTS.iter().position(|m| m == movement).unwrap();
+                    self.writer.write_all(&[MOVEMENT_TAG, movement_ix as u8])?;
+                }
+                InternalUserDiffAction::BeamAim { x, y } => {
+                    self.writer.write_all(&[BEAM_AIM_TAG])?;
+                    write_f32(&mut self.writer, *x)?;
+                    write_f32(&mut self.writer, *y)?;
+                }
+                InternalUserDiffAction::BeamToggle(beam_on) => {
+                    self.writer.write_all(&[BEAM_TOGGLE_TAG, *beam_on as u8])?;
+                }
+                InternalUserDiffAction::Username(username) => {
+                    self.writer.write_all(&[USERNAME_TAG])?;
+                    write_u32(&mut self.writer, username.len() as u32)?;
+                    self.writer.write_all(username.as_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records the checksum of the world's state at the end of a tick.  The recording is flushed
+    /// so that it's complete up to this tick even if the process dies.
+    pub fn record_checksum(&mut self, checksum: u64) -> io::Result<()> {
+        write_u64(&mut self.writer, checksum)?;
+        self.writer.flush()
+    }
+}
+
+/// Folds `bytes` into an FNV-1a hash
+fn fnv_hash(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+fn fnv_hash_u32(hash: u64, val: u32) -> u64 {
+    let mut bytes = Vec::with_capacity(4);
+    write_u32(&mut bytes, val).unwrap();
+    fnv_hash(hash, &bytes)
+}
+
+impl<T> PhysicsWorldInner<T> {
+    /// Calls `f` with the world's recorder if it's being recorded.  If writing to the recording
+    /// fails, recording is stopped.
+    pub fn record<F: FnOnce(&mut Recorder) -> io::Result<()>>(&mut self, f: F) {
+        let res = match self.recorder {
+            Some(ref mut recorder) => f(recorder),
+            None => return,
+        };
+
+        if let Err(err) = res {
+            println!("ERROR: Failed to write to recording; stopping recording: {:?}", err);
+            self.recorder = None;
+        }
+    }
+
+    /// Computes a checksum of the IDs and movement states of all entities in the world along with
+    /// the respawn timers of all dead players.
+    pub fn state_checksum(&self) -> u64 {
+        let mut hash = 0xcbf2_9ce4_8422_2325;
+        for id in self.uuid_map.keys() {
+            hash = fnv_hash(hash, key_to_uuid(id).as_bytes());
+            let (movement, _) = match self.entity_state(id) {
+                Some(state) => state,
+                None => continue,
+            };
+
+            let fields = [
+                movement.pos_x,
+                movement.pos_y,
+                movement.rotation,
+                movement.velocity_x,
+                movement.velocity_y,
+                movement.angular_velocity,
+            ];
+            for field in &fields {
+                hash = fnv_hash_u32(hash, field.to_bits());
+            }
+        }
+
+        for (id, ticks_remaining) in &self.respawn_timers {
+            hash = fnv_hash(hash, key_to_uuid(id).as_bytes());
+            hash = fnv_hash_u32(hash, *ticks_remaining);
+        }
+
+        hash
+    }
+}
+
+impl PhysicsWorldInner<()> {
+    /// Starts recording all inputs applied to this world to a new file at `path`.  This must be
+    /// called right after the world is initialized with `seed` so that the recording starts from
+    /// the same state that a replay will.
+    pub fn start_recording<P: AsRef<Path>>(&mut self, path: P, seed: u64) -> io::Result<()> {
+        self.recorder = Some(Recorder::create(path, seed)?);
+        Ok(())
+    }
+
+    /// Starts recording this world to a new file in `CONF.game.recording_directory` if
+    /// `CONF.game.record_sessions` is set.  `name` identifies the world in the name of the file.
+    pub fn start_session_recording(&mut self, name: &str, seed: u64) {
+        if !CONF.game.record_sessions {
+            return;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time is before the UNIX epoch!");
+        let dir = Path::new(CONF.game.recording_directory);
+        let path = dir.join(format!(
+            "{}-{}-{}.rec",
+            name.replace(|c: char| !c.is_alphanumeric(), "_"),
+            now.as_secs(),
+            seed
+        ));
+
+        let res = fs::create_dir_all(dir).and_then(|_| self.start_recording(&path, seed));
+        match res {
+            Ok(()) => println!("Recording session to {}", path.display()),
+            Err(err) => println!(
+                "ERROR: Unable to start recording to {}: {:?}",
+                path.display(),
+                err
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    /// The file isn't a recording or one of its entries is invalid
+    Malformed,
+    UnsupportedVersion(u16),
+    /// The state of the replayed world differed from the recorded one after the given tick
+    Diverged { tick: u32, expected: u64, actual: u64 },
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        ReplayError::Io(err)
+    }
+}
+
+fn read_diff<R: Read>(reader: &mut R) -> Result<InternalUserDiff, ReplayError> {
+    let id = uuid_to_key(read_uuid(reader)?);
+    let has_render_tick = read_u8(reader)? != 0;
+    let render_tick = read_u32(reader)?;
+    let render_tick = if has_render_tick {
+        Some(render_tick)
+    } else {
+        None
+    };
+    let action = match read_u8(reader)? {
+        MOVEMENT_TAG => {
+            let movement_ix = read_u8(reader)? as usize;
+            let movement = MOVEMENTS.get(movement_ix).ok_or(ReplayError::Malformed)?;
+            InternalUserDiffAction::Movement(*movement)
+        }
+        BEAM_AIM_TAG => InternalUserDiffAction::BeamAim {
+            x: read_f32(reader)?,
+            y: read_f32(reader)?,
+        },
+        BEAM_TOGGLE_TAG => InternalUserDiffAction::BeamToggle(read_u8(reader)? != 0),
+        USERNAME_TAG => {
+            let mut buf = vec![0u8; read_u32(reader)? as usize];
+            reader.read_exact(&mut buf)?;
+            InternalUserDiffAction::Username(
+                String::from_utf8(buf).map_err(|_| ReplayError::Malformed)?,
+            )
+        }
+        _ => return Err(ReplayError::Malformed),
+    };
+
+    Ok(InternalUserDiff {
+        id,
+        action,
+        render_tick,
+    })
+}
+
+/// Rebuilds the world from the recording at `path` and re-applies all of its inputs, verifying
+/// that the world is in the same state as the recorded one after every tick.  Returns the world
+/// as it was at the end of the recording.  A tick that was cut off at the end of the recording
+/// (for example because the server crashed during it) is ignored.
+pub fn replay<P: AsRef<Path>>(path: P) -> Result<PhysicsWorldInner, ReplayError> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(ReplayError::Malformed);
+    }
+    let version = read_u16(&mut reader)?;
+    if version != RECORDING_VERSION {
+        return Err(ReplayError::UnsupportedVersion(version));
+    }
+    let seed = read_u64(&mut reader)?;
+
+    let mut world = PhysicsWorldInner::new();
+    world.initialize(seed);
+
+    loop {
+        let mut tag = [0u8; 1];
+        if reader.read(&mut tag)? == 0 {
+            break;
+        }
+
+        match tag[0] {
+            SPAWN_TAG => {
+                world.spawn_player(read_uuid(&mut reader)?);
+            }
+            DESPAWN_TAG => world.remove_entity(&uuid_to_key(read_uuid(&mut reader)?)),
+            GRAVITATION_TAG => world.set_gravitation(read_u8(&mut reader)? != 0),
+            TICK_TAG => {
+                let update_all = read_u8(&mut reader)? != 0;
+                let diff_count = read_u32(&mut reader)?;
+                let diffs = (0..diff_count)
+                    .map(|_| read_diff(&mut reader))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let expected = match read_u64(&mut reader) {
+                    Ok(checksum) => checksum,
+                    Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => break,
+                    Err(err) => return Err(err.into()),
+                };
+
+                world.tick(diffs, update_all);
+                let actual = world.state_checksum();
+                if actual != expected {
+                    return Err(ReplayError::Diverged {
+                        tick: world.tick_count - 1,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+            _ => return Err(ReplayError::Malformed),
+        }
+    }
+
+    Ok(world)
+}
+
+#[test]
+fn recorded_sessions_replay_identically() {
+    use std::env;
+
+    let seed = 1337;
+    let path = env::temp_dir().join(format!("io-game-replay-test-{}.rec", seed));
+
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    world.initialize(seed);
+    world.start_recording(&path, seed).unwrap();
+
+    // Drawing these from the world's RNG would desync it from the replay, which doesn't know about
+    // them
+    let (player_uuid, other_uuid) = (
+        Uuid::from_random_bytes([1; 16]),
+        Uuid::from_random_bytes([2; 16]),
+    );
+    let (player_id, other_id) = (uuid_to_key(player_uuid), uuid_to_key(other_uuid));
+    world.spawn_player(player_uuid);
+    world.spawn_player(other_uuid);
+
+    let inputs = |tick: u32| {
+        let movement = MOVEMENTS[(tick as usize / 4) % MOVEMENTS.len()];
+        let action = match tick % 4 {
+            0 => InternalUserDiffAction::Movement(movement),
+            1 => InternalUserDiffAction::BeamAim {
+                x: tick as f32,
+                y: -(tick as f32),
+            },
+            2 => InternalUserDiffAction::BeamToggle(tick % 8 == 2),
+            _ => InternalUserDiffAction::Username(format!("player {}", tick)),
+        };
+        vec![InternalUserDiff {
+            id: player_id.clone(),
+            action,
+            render_tick: Some(tick.saturating_sub(5)),
+        }]
+    };
+
+    for tick in 0..30 {
+        world.tick(inputs(tick), tick % 10 == 0);
+    }
+    world.set_gravitation(true);
+    for tick in 30..60 {
+        world.tick(inputs(tick), tick % 10 == 0);
+    }
+    world.remove_entity(&other_id);
+    for tick in 60..90 {
+        world.tick(inputs(tick), false);
+    }
+    // Make sure the recording is flushed and closed
+    world.recorder = None;
+
+    let replayed = replay(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(replayed.tick_count, world.tick_count);
+    assert_eq!(replayed.state_checksum(), world.state_checksum());
+    assert!(!replayed.uuid_map.contains_key(&other_id));
+    assert!(replayed.gravitation_enabled);
+}
@@ -0,0 +1,103 @@
+//! Chooses where new players are spawned into the world so that they don't end up inside of
+//! other entities.
+
+use std::f32::INFINITY;
+
+use nalgebra::{Point2, Vector2};
+use ncollide2d::bounding_volume::AABB;
+use ncollide2d::world::CollisionGroups;
+use rand::Rng;
+
+use conf::CONF;
+
+use super::entities::DEFAULT_PLAYER_SIZE;
+use super::world::PhysicsWorldInner;
+
+impl<T> PhysicsWorldInner<T> {
+    /// Returns the number of colliders whose bounding boxes overlap the area that a player
+    /// spawned at `pos` would occupy, including the configured clearance around it.  The broad
+    /// phase must be up to date; see `PhysicsWorldInner::update_broad_phase`.
+    pub fn count_spawn_obstructions(&self, pos: &Point2<f32>) -> usize {
+        let half_extent = DEFAULT_PLAYER_SIZE / 2. + CONF.game.player_spawn.clearance;
+        let half_extents = Vector2::new(half_extent, half_extent);
+        let aabb = AABB::new(*pos - half_extents, *pos + half_extents);
+
+        self.world
+            .collision_world()
+            .interferences_with_aabb(&aabb, &CollisionGroups::new())
+            .count()
+    }
+
+    /// Returns the distance from `pos` to the closest player in the world, or infinity if there
+    /// are no players.
+    fn distance_to_nearest_player(&self, pos: &Point2<f32>) -> f32 {
+        self.user_handles
+            .iter()
+            .filter_map(|(body_handle, _, _)| self.world.rigid_body(*body_handle))
+            .map(|body| (body.position().translation.vector - pos.coords).norm())
+            .fold(INFINITY, |min_distance, distance| min_distance.min(distance))
+    }
+
+    /// Picks a point within the world's bounds at which to spawn a player.  The broad phase is
+    /// brought up to date with any entities spawned since the last step, and then a set of random
+    /// candidate points is checked against it; the free candidate furthest from other players is
+    /// chosen.  If the world is so crowded that none of the candidates are free, the one with the
+    /// fewest obstructions is used instead.
+    pub fn find_spawn_point(&mut self) -> Point2<f32> {
+        self.update_broad_phase();
+
+        let margin = DEFAULT_PLAYER_SIZE / 2. + CONF.game.player_spawn.clearance;
+        let (min_x, max_x) = (CONF.game.world_min_x + margin, CONF.game.world_max_x - margin);
+        let (min_y, max_y) = (CONF.game.world_min_y + margin, CONF.game.world_max_y - margin);
+
+        let mut best: Option<(Point2<f32>, usize, f32)> = None;
+        for _ in 0..CONF.game.player_spawn.candidate_count.max(1) {
+            let candidate = Point2::new(
+                self.rng.gen_range(min_x, max_x),
+                self.rng.gen_range(min_y, max_y),
+            );
+            let obstructions = self.count_spawn_obstructions(&candidate);
+            let distance = self.distance_to_nearest_player(&candidate);
+
+            let is_better = match best {
+                None => true,
+                Some((_, best_obstructions, best_distance)) => {
+                    obstructions < best_obstructions
+                        || (obstructions == best_obstructions && distance > best_distance)
+                }
+            };
+            if is_better {
+                best = Some((candidate, obstructions, distance));
+            }
+        }
+
+        best.map(|(pos, _, _)| pos).unwrap()
+    }
+}
+
+#[test]
+fn spawn_points_avoid_other_entities() {
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    world.initialize(42);
+
+    for _ in 0..10 {
+        let pos = world.find_spawn_point();
+        assert!(pos.x >= CONF.game.world_min_x && pos.x <= CONF.game.world_max_x);
+        assert!(pos.y >= CONF.game.world_min_y && pos.y <= CONF.game.world_max_y);
+        assert_eq!(world.count_spawn_obstructions(&pos), 0);
+    }
+}
+
+#[test]
+fn players_spawned_between_steps_dont_overlap() {
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    world.initialize(42);
+
+    // Both players join before the world is ever stepped
+    let (first_id, second_id) = (world.gen_uuid(), world.gen_uuid());
+    let (first, _) = world.spawn_player(first_id);
+    let (second, _) = world.spawn_player(second_id);
+
+    let min_distance = DEFAULT_PLAYER_SIZE + CONF.game.player_spawn.clearance;
+    assert!((second - first).norm() > min_distance);
+}
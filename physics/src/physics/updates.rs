@@ -0,0 +1,128 @@
+//! Defines the updates produced by the physics world during a tick.  These are independent of the
+//! transport that is used to deliver them to clients; the Elixir NIFs and the standalone server
+//! each convert them into their own representations.
+
+use std::f32::consts::PI;
+
+use nalgebra::{Isometry2, Point2, Vector2};
+use nphysics2d::algebra::Velocity2;
+
+use conf::CONF;
+
+use super::beams::BeamHit;
+use super::world::EntityKey;
+use super::Movement;
+
+/// The dynamic physics properties of an entity: its position and velocity, both linear and angular
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MovementState {
+    pub pos_x: f32,
+    pub pos_y: f32,
+    pub rotation: f32,
+    pub velocity_x: f32,
+    pub velocity_y: f32,
+    pub angular_velocity: f32,
+}
+
+impl MovementState {
+    pub fn new(pos: &Isometry2<f32>, velocity: &Velocity2<f32>) -> Self {
+        MovementState {
+            pos_x: pos.translation.vector.x,
+            pos_y: pos.translation.vector.y,
+            rotation: pos.rotation.angle(),
+            velocity_x: velocity.linear.x,
+            velocity_y: velocity.linear.y,
+            angular_velocity: velocity.angular,
+        }
+    }
+
+    /// Returns `true` if this state has drifted far enough from `prev` that clients need to be
+    /// sent a new movement update, as determined by `CONF.network.movement_delta`.
+    pub fn has_drifted_from(&self, prev: &MovementState) -> bool {
+        let conf = &CONF.network.movement_delta;
+        let pos_diff = Vector2::new(self.pos_x - prev.pos_x, self.pos_y - prev.pos_y);
+        let velocity_diff = Vector2::new(
+            self.velocity_x - prev.velocity_x,
+            self.velocity_y - prev.velocity_y,
+        );
+        let rotation_diff = (self.rotation - prev.rotation).abs() % (2. * PI);
+        let rotation_diff = rotation_diff.min(2. * PI - rotation_diff);
+
+        pos_diff.norm() > conf.position
+            || rotation_diff > conf.rotation
+            || velocity_diff.norm() > conf.velocity
+            || (self.angular_velocity - prev.angular_velocity).abs() > conf.angular_velocity
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum WorldUpdate {
+    Movement {
+        id: EntityKey,
+        movement: MovementState,
+    },
+    /// A player's beam started hitting something else or its hit point moved
+    BeamHit {
+        player_id: EntityKey,
+        hit: BeamHit,
+    },
+    Username {
+        id: EntityKey,
+        username: String,
+    },
+    PlayerMovement {
+        id: EntityKey,
+        movement: Movement,
+    },
+    BeamToggle {
+        id: EntityKey,
+        beam_on: bool,
+    },
+    BeamAim {
+        id: EntityKey,
+        aim: Point2<f32>,
+    },
+    /// A mineable object was mined until it ran out of health and has been removed from the world.
+    /// Its removal is reported separately by an `EntityDeleted` update.
+    ObjectDepleted {
+        id: EntityKey,
+        miner_id: EntityKey,
+    },
+    /// A new entity was spawned into the world
+    EntityCreated {
+        id: EntityKey,
+    },
+    /// An entity was removed from the world
+    EntityDeleted {
+        id: EntityKey,
+    },
+    HealthChange {
+        id: EntityKey,
+        health: f32,
+    },
+    /// A player's health ran out.  Their entity is removed from the world until they respawn.
+    PlayerDeath {
+        id: EntityKey,
+        /// The player whose beam dealt the killing blow, if any
+        killer_id: Option<EntityKey>,
+    },
+}
+
+impl WorldUpdate {
+    /// Returns the ID of the entity that this update is about
+    pub fn entity_id(&self) -> &EntityKey {
+        match self {
+            WorldUpdate::Movement { id, .. }
+            | WorldUpdate::Username { id, .. }
+            | WorldUpdate::PlayerMovement { id, .. }
+            | WorldUpdate::BeamToggle { id, .. }
+            | WorldUpdate::BeamAim { id, .. }
+            | WorldUpdate::ObjectDepleted { id, .. }
+            | WorldUpdate::EntityCreated { id }
+            | WorldUpdate::EntityDeleted { id }
+            | WorldUpdate::HealthChange { id, .. }
+            | WorldUpdate::PlayerDeath { id, .. } => id,
+            WorldUpdate::BeamHit { player_id, .. } => player_id,
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Defines the inputs that users send to the server between ticks and how they are applied to the
+//! entities in the physics world.
+
+use nalgebra::Point2;
+
+use super::entities::{Entity, EntityHandles, PlayerEntity};
+use super::updates::WorldUpdate;
+use super::world::{EntityKey, PhysicsWorldInner};
+use super::Movement;
+
+pub struct InternalUserDiff {
+    pub id: EntityKey,
+    pub action: InternalUserDiffAction,
+    /// The tick of the world that the user was seeing when they sent this input, if known.  See
+    /// the `lag_compensation` module.
+    pub render_tick: Option<u32>,
+}
+
+/// Holds a change between the status of a user between ticks.  This status is different than the
+/// physics state held by the physics engine which consists of position, velocity, rotation, etc.
+/// and instead
+pub enum InternalUserDiffAction {
+    Movement(Movement),
+    BeamAim { x: f32, y: f32 },
+    BeamToggle(bool),
+    Username(String),
+}
+
+impl<T> PhysicsWorldInner<T> {
+    /// Applies a single user input to the world, pushing any updates that need to be sent to
+    /// clients as a result into `updates`.
+    pub fn apply_diff(&mut self, diff: InternalUserDiff, updates: &mut Vec<WorldUpdate>) {
+        let uuid = diff.id;
+
+        // Dead players can't do anything until they respawn
+        if self.respawn_timers.contains_key(&uuid) {
+            return;
+        }
+
+        if let Some(render_tick) = diff.render_tick {
+            self.render_ticks.insert(uuid.clone(), render_tick);
+        }
+
+        let entity = match self.uuid_map.get_mut(&uuid) {
+            Some(EntityHandles { entity, .. }) => entity,
+            None => {
+                println!(
+                    "ERROR: Received update for user with id {} but no such user exists!",
+                    uuid
+                );
+                return;
+            }
+        };
+
+        let expected_player = || println!("ERROR: Received invalid update for non-player entity!");
+
+        match diff.action {
+            InternalUserDiffAction::Movement(new_movement) => match *entity {
+                Entity::Player(PlayerEntity {
+                    ref mut movement, ..
+                }) => {
+                    *movement = new_movement;
+                    updates.push(WorldUpdate::PlayerMovement {
+                        id: uuid,
+                        movement: new_movement,
+                    })
+                }
+                _ => expected_player(),
+            },
+            InternalUserDiffAction::BeamAim { x, y } => {
+                let PlayerEntity { beam_aim, .. } = match *entity {
+                    Entity::Player(ref mut player) => player,
+                    _ => {
+                        expected_player();
+                        return;
+                    }
+                };
+
+                // The beam itself is cast toward the new aim point during the next tick
+                *beam_aim = Point2::new(x, y);
+                updates.push(WorldUpdate::BeamAim {
+                    id: uuid,
+                    aim: Point2::new(x, y),
+                });
+            }
+
+            InternalUserDiffAction::BeamToggle(new_beam_on) => {
+                let PlayerEntity { beam_on, .. } = match *entity {
+                    Entity::Player(ref mut player) => player,
+                    _ => {
+                        expected_player();
+                        return;
+                    }
+                };
+
+                if *beam_on == new_beam_on {
+                    let state = if new_beam_on { "on" } else { "off" };
+                    println!("WARN: Received message to turn beam {0} but it was already {0}", state);
+                    return;
+                }
+                *beam_on = new_beam_on;
+                if !new_beam_on {
+                    // Hits are only stored for beams that are on; see `update_beam_hits`
+                    self.beam_hits.remove(&uuid);
+                }
+
+                updates.push(WorldUpdate::BeamToggle {
+                    id: uuid,
+                    beam_on: new_beam_on,
+                });
+            }
+            InternalUserDiffAction::Username(username) => {
+                updates.push(WorldUpdate::Username { id: uuid, username });
+            }
+        }
+    }
+}
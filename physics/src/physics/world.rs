@@ -1,19 +1,27 @@
 use conf::CONF;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
-use nalgebra::{Isometry2, Vector2};
+use nalgebra::{Isometry2, Point2, Vector2};
+use ncollide2d::events::ContactEvent;
 use nphysics2d::algebra::Velocity2;
 use nphysics2d::force_generator::{ForceGenerator, ForceGeneratorHandle};
-use nphysics2d::object::{BodyHandle, BodySet, BodyStatus, ColliderHandle, Material, RigidBody};
+use nphysics2d::object::{Body, BodyHandle, BodySet, BodyStatus, ColliderHandle, RigidBody};
 use nphysics2d::solver::{IntegrationParameters, SignoriniModel};
 use nphysics2d::volumetric::Volumetric;
 use nphysics2d::world::World;
 use uuid::Uuid;
 
+use super::beams::BeamHit;
 use super::entities::{Entity, EntityHandles, EntitySpawn, PlayerEntity};
+use super::force_fields::{load_map, ForceField};
+use super::lag_compensation::IsometrySnapshot;
+use super::recording::Recorder;
+use super::updates::{MovementState, WorldUpdate};
+use super::user_diff::InternalUserDiff;
+use super::wrap::{entity_collision_groups, Ghost};
 use super::Movement;
-use worldgen::get_initial_entities;
+use worldgen::{gen_uuid, get_initial_entities, get_initial_force_fields, world_rng, WorldRng};
 
 pub const COLLIDER_MARGIN: f32 = CONF.physics.collider_margin;
 const WORLD_MISSING_ERR: &'static str = "Entity in UUID map but not the world!";
@@ -28,6 +36,11 @@ mod cond {
     pub fn uuid_to_key(id: Uuid) -> EntityKey {
         id.to_string()
     }
+
+    #[inline(always)]
+    pub fn key_to_uuid(key: &EntityKey) -> Uuid {
+        key.parse().expect("Invalid UUID used as entity key!")
+    }
 }
 
 #[cfg(not(feature = "elixir-interop"))]
@@ -40,9 +53,14 @@ mod cond {
     pub fn uuid_to_key(id: Uuid) -> EntityKey {
         id
     }
+
+    #[inline(always)]
+    pub fn key_to_uuid(key: &EntityKey) -> Uuid {
+        *key
+    }
 }
 
-use self::cond::*;
+pub use self::cond::*;
 
 pub struct PhysicsWorldInner<T = ()> {
     /// Maps UUIDs to internal physics entity handles
@@ -53,16 +71,117 @@ pub struct PhysicsWorldInner<T = ()> {
     pub world: World<f32>,
     /// A list containing handles to all player entities, used to apply movement and friction
     pub user_handles: Vec<(BodyHandle, EntityKey, ForceGeneratorHandle)>,
-    /// Maps the collider handles of beam sensors to the User entities that own them
-    pub beam_sensors: BTreeMap<ColliderHandle, EntityKey>,
+    /// Regions of the world that push bodies around.  See the `force_fields` module.
+    pub force_fields: Vec<ForceField>,
+    /// The handle of the force generator that applies `force_fields`, which is re-created every
+    /// step
+    pub force_field_generator: Option<ForceGeneratorHandle>,
+    /// Whether heavy objects attract each other and players.  See the `gravitation` module.
+    pub gravitation_enabled: bool,
+    /// The handle of the force generator that applies gravitation, which is re-created every step
+    pub gravitation_generator: Option<ForceGeneratorHandle>,
+    /// Maps the IDs of players with active beams to what their beams hit as of the last tick.  See
+    /// the `beams` module.
+    pub beam_hits: BTreeMap<EntityKey, BeamHit>,
+    /// Creation and deletion updates for all entities spawned into or removed from the world
+    /// during the current tick.  This is `None` outside of ticks, since entities spawned or
+    /// removed in between them are announced to clients by whoever spawned or removed them.
+    pub lifecycle_updates: Option<Vec<WorldUpdate>>,
+    /// Maps the IDs of dead players to the number of ticks remaining until they respawn
+    pub respawn_timers: BTreeMap<EntityKey, u32>,
+    /// Maps the IDs of entities near the edges of a wrapping world to the ghost copies of their
+    /// colliders on the other side of it.  See the `wrap` module.
+    pub ghost_colliders: BTreeMap<EntityKey, Vec<Ghost>>,
+    /// Maps the IDs of players to the IDs of all entities that they were last told about.  See the
+    /// `interest` module.
+    pub interest_sets: BTreeMap<EntityKey, BTreeSet<EntityKey>>,
+    /// The movement state most recently sent to clients for each entity, used to skip sending
+    /// updates for entities that haven't moved noticeably since
+    pub sent_movements: BTreeMap<EntityKey, MovementState>,
+    /// The tick during which movement updates were last sent for all entities regardless of drift,
+    /// or `None` if they haven't been since the world was created or cleared
+    pub last_refresh_tick: Option<u32>,
+    /// Maps the IDs of players to the tick that they reported rendering with their latest input
+    pub render_ticks: BTreeMap<EntityKey, u32>,
+    /// The isometries of all entities during the last few ticks, oldest first.  See the
+    /// `lag_compensation` module.
+    pub isometry_history: VecDeque<IsometrySnapshot>,
+    /// The number of ticks that have been run in this world
+    pub tick_count: u32,
+    /// The source of all randomness used by the world after it has been generated, seeded so that
+    /// runs with the same seed and inputs are identical.  Only the world's own handling of its
+    /// inputs may draw from it; see the `recording` module.
+    pub rng: WorldRng,
+    /// The seed that `rng` was created from, kept so that its state can be saved and restored
+    pub rng_seed: u64,
+    /// Records all inputs applied to the world if it's being recorded.  See the `recording`
+    /// module.
+    pub recorder: Option<Recorder>,
 }
 
 impl PhysicsWorldInner<()> {
-    pub fn initialize(&mut self) {
-        // Populate the world with initial entities
-        for entity_spawn in get_initial_entities() {
-            self.spawn_entity(Uuid::new_v4(), entity_spawn);
+    /// Populates the world with its initial entities and force fields, including any from the
+    /// configured map file.  Entity generation and IDs are derived from `seed`, so two worlds
+    /// initialized with the same seed are identical.
+    pub fn initialize(&mut self, seed: u64) {
+        self.rng = world_rng(seed);
+        self.rng_seed = seed;
+
+        for entity_spawn in get_initial_entities(seed) {
+            let uuid = self.gen_uuid();
+            self.spawn_entity(uuid, entity_spawn);
+        }
+
+        self.force_fields = get_initial_force_fields(seed);
+        if !CONF.game.map_file.is_empty() {
+            match load_map(CONF.game.map_file) {
+                Ok(force_fields) => self.force_fields.extend(force_fields),
+                Err(err) => println!(
+                    "ERROR: Unable to load map file {}: {:?}",
+                    CONF.game.map_file, err
+                ),
+            }
+        }
+    }
+
+    /// Adds a new player into the world with the given UUID, returning its center of mass and
+    /// initial movement state.
+    pub fn spawn_player(&mut self, uuid: Uuid) -> (Point2<f32>, MovementState) {
+        // Players spawned during ticks are respawns, which happen again on their own during replays
+        if self.lifecycle_updates.is_none() {
+            self.record(|recorder| recorder.record_spawn(&uuid));
+        }
+
+        let pos = Isometry2::new(self.find_spawn_point().coords, 0.0);
+        let velocity = Velocity2::zero();
+
+        let entity_spawn = EntitySpawn {
+            entity: Entity::Player(PlayerEntity::default()),
+            isometry: pos,
+            velocity,
+            data: (),
+            body_status: BodyStatus::Dynamic,
+            material: None,
+        };
+
+        self.spawn_entity(uuid, entity_spawn);
+        let key = uuid_to_key(uuid);
+
+        // Players joining outside of ticks are sent a snapshot of the entities in their view, built
+        // from this set, and are announced to everyone else by the caller
+        if self.lifecycle_updates.is_none() {
+            self.update_broad_phase();
+            let view = self.entities_in_view(&Point2::from_coordinates(pos.translation.vector));
+            self.interest_sets.entry(key.clone()).or_insert(view);
+            for view in self.interest_sets.values_mut() {
+                view.insert(key.clone());
+            }
         }
+
+        let body_handle = self.uuid_map.get(&key).unwrap().body_handle;
+        let center_of_mass = self.world.rigid_body(body_handle).unwrap().center_of_mass();
+
+        (center_of_mass, MovementState::new(&pos, &velocity))
     }
 }
 
@@ -116,10 +235,37 @@ impl<T> PhysicsWorldInner<T> {
             handle_map: BTreeMap::new(),
             world,
             user_handles: Vec::new(),
-            beam_sensors: BTreeMap::new(),
+            force_fields: Vec::new(),
+            force_field_generator: None,
+            gravitation_enabled: CONF.physics.gravitation.enabled_by_default,
+            gravitation_generator: None,
+            beam_hits: BTreeMap::new(),
+            lifecycle_updates: None,
+            respawn_timers: BTreeMap::new(),
+            ghost_colliders: BTreeMap::new(),
+            interest_sets: BTreeMap::new(),
+            sent_movements: BTreeMap::new(),
+            last_refresh_tick: None,
+            render_ticks: BTreeMap::new(),
+            isometry_history: VecDeque::new(),
+            tick_count: 0,
+            rng: world_rng(0),
+            rng_seed: 0,
+            recorder: None,
         }
     }
 
+    /// Generates a new entity UUID deterministically from the world's RNG
+    pub fn gen_uuid(&mut self) -> Uuid {
+        gen_uuid(&mut self.rng)
+    }
+
+    /// Brings the broad phase up to date with the entities spawned since the last step so that it
+    /// can be queried in between steps
+    pub fn update_broad_phase(&mut self) {
+        self.world.collision_world_mut().update();
+    }
+
     /// Apply movement updates to all user entities based on their input and apply friction.  Then,
     /// step the underlying physics world for one tick of the simulation.
     pub fn step(&mut self) {
@@ -154,8 +300,20 @@ impl<T> PhysicsWorldInner<T> {
             *force_gen_handle = new_force_gen_handle;
         }
 
+        self.update_force_fields();
+        self.update_gravitation();
+
+        if CONF.game.wrap_world {
+            self.update_ghosts();
+        }
+
         // Step the physics simulation
         self.world.step();
+
+        if CONF.game.wrap_world {
+            self.apply_ghost_impulses();
+            self.wrap_bodies();
+        }
     }
 
     pub fn spawn_entity(&mut self, uuid: Uuid, entity_data: EntitySpawn<T>) {
@@ -165,12 +323,14 @@ impl<T> PhysicsWorldInner<T> {
             velocity,
             data,
             body_status,
+            material,
         } = entity_data;
+        let material = material.unwrap_or_else(|| entity.default_material());
 
         // `ShapeHandle` implements `AsRef<Shape>`, and `Shape` implements `Volumetric` which has the
         // `inertia()` and `center_of_mass()` functions.  Yeah.
         let shape_handle = entity.get_shape_handle();
-        let inertia = shape_handle.inertia(entity.get_density());
+        let inertia = shape_handle.inertia(material.density);
         let center_of_mass = shape_handle.center_of_mass();
 
         let (collider_handle, body_handle) = if body_status == BodyStatus::Static {
@@ -179,7 +339,7 @@ impl<T> PhysicsWorldInner<T> {
                 shape_handle,
                 BodyHandle::ground(),
                 isometry,
-                Material::default(),
+                material.collider_material(),
             );
 
             (collider_handle, BodyHandle::ground())
@@ -195,7 +355,7 @@ impl<T> PhysicsWorldInner<T> {
                 shape_handle,
                 body_handle,
                 Isometry2::identity(),
-                Material::default(),
+                material.collider_material(),
             );
 
             if let Entity::Player(_) = entity {
@@ -208,32 +368,53 @@ impl<T> PhysicsWorldInner<T> {
 
             (collider_handle, body_handle)
         };
+        self.world
+            .collision_world_mut()
+            .set_collision_groups(collider_handle, entity_collision_groups());
         let handles = EntityHandles {
             collider_handle,
             body_handle,
-            beam_handle: None,
             entity,
             data,
+            material,
         };
         self.uuid_map.insert(uuid_to_key(uuid), handles);
         self.handle_map.insert(collider_handle, uuid_to_key(uuid));
+
+        if let Some(ref mut lifecycle_updates) = self.lifecycle_updates {
+            lifecycle_updates.push(WorldUpdate::EntityCreated {
+                id: uuid_to_key(uuid),
+            });
+        }
     }
 
     /// Removes an entity from both the physics world as well as all maps.
     pub fn remove_entity(&mut self, entity_id: &EntityKey) {
+        if self.lifecycle_updates.is_none() && self.recorder.is_some() {
+            let uuid = key_to_uuid(entity_id);
+            self.record(|recorder| recorder.record_despawn(&uuid));
+        }
+
         let EntityHandles {
             collider_handle,
             body_handle,
-            beam_handle,
             ..
         } = match self.uuid_map.remove(entity_id) {
             Some(handles) => handles,
+            // Dead players waiting to respawn aren't in the world, so just cancel the respawn
+            None if self.respawn_timers.remove(entity_id).is_some() => return,
             None => {
                 println!("ERROR: Tried to remove entity but it didn't exist in the UUID map");
                 return;
             }
         };
 
+        if let Some(ghosts) = self.ghost_colliders.remove(entity_id) {
+            for ghost in ghosts {
+                self.remove_ghost(ghost);
+            }
+        }
+
         self.handle_map.remove(&collider_handle);
         self.world.remove_colliders(&[collider_handle]);
         self.world.remove_bodies(&[body_handle]);
@@ -249,9 +430,38 @@ impl<T> PhysicsWorldInner<T> {
             self.world.remove_force_generator(force_gen_key);
         }
 
-        if let Some(beam_handle) = beam_handle {
-            self.world.remove_colliders(&[beam_handle]);
-            self.beam_sensors.remove(&beam_handle);
+        self.sent_movements.remove(entity_id);
+        self.render_ticks.remove(entity_id);
+        self.beam_hits.remove(entity_id);
+        // Beams that were hitting the entity don't hit anything until they're cast again
+        for hit in self.beam_hits.values_mut() {
+            if hit.target_id.as_ref() == Some(entity_id) {
+                hit.target_id = None;
+            }
+        }
+
+        // Entities removed outside of ticks are announced to everyone by whoever removed them
+        if self.lifecycle_updates.is_none() {
+            self.interest_sets.remove(entity_id);
+            for view in self.interest_sets.values_mut() {
+                view.remove(entity_id);
+            }
+        }
+
+        if let Some(ref mut lifecycle_updates) = self.lifecycle_updates {
+            // Entities that were created and removed within the same tick are never seen by clients
+            let creation_ix = lifecycle_updates.iter().position(|update| match update {
+                WorldUpdate::EntityCreated { id } => id == entity_id,
+                _ => false,
+            });
+            match creation_ix {
+                Some(creation_ix) => {
+                    lifecycle_updates.remove(creation_ix);
+                }
+                None => lifecycle_updates.push(WorldUpdate::EntityDeleted {
+                    id: entity_id.clone(),
+                }),
+            }
         }
     }
 
@@ -310,28 +520,329 @@ impl<T> PhysicsWorldInner<T> {
         rigid_body.set_position(interpolated_position);
     }
 
+    /// Returns the current movement state and center of mass of the entity with the given ID.
+    /// Static entities attached to the ground have zero velocity and their center of mass at the
+    /// origin.
+    pub fn entity_state(&self, entity_id: &EntityKey) -> Option<(MovementState, Point2<f32>)> {
+        let EntityHandles {
+            collider_handle, ..
+        } = self.uuid_map.get(entity_id)?;
+        let collider = self.world.collider(*collider_handle)?;
+        let isometry: &Isometry2<f32> = collider.position();
+
+        let (velocity, center_of_mass) = match self.world.body(collider.data().body()) {
+            Body::RigidBody(rigid_body) => {
+                (rigid_body.velocity().clone(), rigid_body.center_of_mass())
+            }
+            Body::Multibody(_) => unimplemented!(),
+            Body::Ground(_) => (Velocity2::new(Vector2::zeros(), 0.0), Point2::origin()),
+        };
+
+        Some((MovementState::new(isometry, &velocity), center_of_mass))
+    }
+
+    fn movement_update(&self, collider_handle: ColliderHandle) -> Option<WorldUpdate> {
+        self.world.collider(collider_handle)?;
+        let uuid = self
+            .handle_map
+            .get(&collider_handle)
+            .expect("`ColliderHandle` wasn't in the `handle_map`!");
+        // Ghost colliders are positioned on the other side of the world from their entities, so
+        // always report the position of the entity's own collider.
+        let collider_handle = self.uuid_map.get(uuid)?.collider_handle;
+        let collider = self.world.collider(collider_handle)?;
+        let body_handle = self.world.collider_body_handle(collider_handle)?;
+        let velocity = self.world.rigid_body(body_handle)?.velocity();
+
+        Some(WorldUpdate::Movement {
+            id: uuid.clone(),
+            movement: MovementState::new(collider.position(), velocity),
+        })
+    }
+
+    /// Returns the linear velocities of all dynamic entities in the world so that they can be
+    /// compared to their velocities after the next step by `impacts`.
+    pub fn entity_velocities(&self) -> BTreeMap<EntityKey, Vector2<f32>> {
+        self.uuid_map
+            .iter()
+            .filter_map(|(id, handles)| {
+                let body = self.world.rigid_body(handles.body_handle)?;
+                Some((id.clone(), body.velocity().linear))
+            }).collect()
+    }
+
+    /// Finds all entities that started colliding with something during the last step, returning
+    /// their IDs along with the magnitude of the change in their velocity compared to
+    /// `prev_velocities`.  This is used to determine how hard an entity was struck.
+    pub fn impacts(
+        &self,
+        prev_velocities: &BTreeMap<EntityKey, Vector2<f32>>,
+    ) -> Vec<(EntityKey, f32)> {
+        let mut impacts: Vec<(EntityKey, f32)> = Vec::new();
+
+        for contact_evt in self.world.contact_events() {
+            let (handle_1, handle_2) = match contact_evt {
+                ContactEvent::Started(handle_1, handle_2) => (handle_1, handle_2),
+                ContactEvent::Stopped(_, _) => continue,
+            };
+
+            for handle in &[handle_1, handle_2] {
+                let id = match self.handle_map.get(*handle) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                if impacts.iter().any(|(impacted_id, _)| impacted_id == id) {
+                    continue;
+                }
+                let prev_velocity = match prev_velocities.get(id) {
+                    Some(velocity) => velocity,
+                    None => continue,
+                };
+                let velocity = match self.world.rigid_body(self.uuid_map[id].body_handle) {
+                    Some(body) => body.velocity().linear,
+                    None => continue,
+                };
+
+                impacts.push((id.clone(), (velocity - prev_velocity).norm()));
+            }
+        }
+
+        impacts
+    }
+
     /// Removes all entities from this world
     pub fn clear(&mut self) {
+        self.remove_ghosts();
+
         for (
             _,
             EntityHandles {
                 collider_handle,
                 body_handle,
-                beam_handle,
                 ..
             },
         ) in self.uuid_map.iter()
         {
             self.world.remove_colliders(&[*collider_handle]);
             self.world.remove_bodies(&[*body_handle]);
-            if let Some(beam_handle) = beam_handle {
-                self.world.remove_colliders(&[*beam_handle]);
-            }
+        }
+
+        for (_, _, force_gen_handle) in self.user_handles.drain(..) {
+            self.world.remove_force_generator(force_gen_handle);
+        }
+        // Force fields aren't entities and stay in place, but the generator applying them refers
+        // to bodies that are being removed
+        if let Some(force_gen_handle) = self.force_field_generator.take() {
+            self.world.remove_force_generator(force_gen_handle);
+        }
+        if let Some(force_gen_handle) = self.gravitation_generator.take() {
+            self.world.remove_force_generator(force_gen_handle);
         }
 
         self.uuid_map.clear();
         self.handle_map.clear();
-        self.user_handles.clear();
-        self.beam_sensors.clear();
+        self.beam_hits.clear();
+        self.respawn_timers.clear();
+        self.interest_sets.clear();
+        self.sent_movements.clear();
+        self.last_refresh_tick = None;
+        self.render_ticks.clear();
+        self.isometry_history.clear();
+    }
+}
+
+impl PhysicsWorldInner<()> {
+    /// Applies all user inputs received since the last tick, steps the simulation, and returns a
+    /// set of updates that need to be sent to users.  If `update_all` is set, movement updates
+    /// are created for all entities rather than only the ones involved in collisions.
+    pub fn tick(&mut self, diffs: Vec<InternalUserDiff>, update_all: bool) -> Vec<WorldUpdate> {
+        self.record(|recorder| recorder.record_tick(update_all, &diffs));
+
+        let mut updates = Vec::new();
+        self.lifecycle_updates = Some(Vec::new());
+        self.respawn_players();
+
+        for diff in diffs {
+            self.apply_diff(diff, &mut updates);
+        }
+
+        // Apply friction and movement updates for all user entities
+        let prev_velocities = self.entity_velocities();
+        self.step();
+        self.record_isometries();
+
+        let beam_updates = self.update_beam_hits();
+        updates.extend(beam_updates);
+
+        self.apply_beam_damage(&mut updates);
+        self.apply_player_beam_damage(&mut updates);
+        let impacts = self.impacts(&prev_velocities);
+        self.fracture_impacted_objects(&impacts);
+        self.damage_impacted_players(&impacts, &mut updates);
+
+        // Let clients know about entities that were spawned or removed during this tick before
+        // sending any movement updates for them
+        if let Some(lifecycle_updates) = self.lifecycle_updates.take() {
+            updates.extend(lifecycle_updates);
+        }
+
+        let mut movement_updates = Vec::new();
+        if update_all {
+            // Create position updates for all entities that have drifted from the last update sent
+            // for them, or for every entity if it's time for a refresh.  Ticks that update all
+            // entities are chosen by the caller and needn't line up with `tick_count`, so refreshes
+            // happen on the first one after the interval has passed.
+            let refresh = match self.last_refresh_tick {
+                Some(last_refresh_tick) => {
+                    self.tick_count.wrapping_sub(last_refresh_tick)
+                        >= CONF.network.movement_delta.refresh_interval_ticks
+                }
+                None => true,
+            };
+            if refresh {
+                self.last_refresh_tick = Some(self.tick_count);
+            }
+            for (id, handles) in &self.uuid_map {
+                let update = match self.movement_update(handles.collider_handle) {
+                    Some(update) => update,
+                    None => continue,
+                };
+                let drifted = match (&update, self.sent_movements.get(id)) {
+                    (WorldUpdate::Movement { movement, .. }, Some(prev_movement)) => {
+                        movement.has_drifted_from(prev_movement)
+                    }
+                    _ => true,
+                };
+
+                if refresh || drifted {
+                    movement_updates.push(update);
+                }
+            }
+        } else {
+            // Create position events for all entities that have just been involved in a collision
+            for contact_evt in self.world.contact_events() {
+                match contact_evt {
+                    ContactEvent::Started(handle_1, handle_2)
+                    | ContactEvent::Stopped(handle_1, handle_2) => {
+                        for handle in &[handle_1, handle_2] {
+                            if let Some(update) = self.movement_update(**handle) {
+                                movement_updates.push(update);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        for update in &movement_updates {
+            if let WorldUpdate::Movement { id, movement } = update {
+                self.sent_movements.insert(id.clone(), *movement);
+            }
+        }
+        updates.extend(movement_updates);
+
+        self.tick_count += 1;
+        if self.recorder.is_some() {
+            let checksum = self.state_checksum();
+            self.record(|recorder| recorder.record_checksum(checksum));
+        }
+
+        updates
+    }
+}
+
+/// Spawns an asteroid with the given shape at `pos` for tests, returning its ID
+#[cfg(test)]
+pub fn spawn_test_object(
+    world: &mut PhysicsWorldInner,
+    shape: super::entities::ObjectShape,
+    pos: Vector2<f32>,
+) -> EntityKey {
+    use super::entities::{builtin_archetype, ObjectEntity};
+
+    let uuid = world.gen_uuid();
+    world.spawn_entity(
+        uuid,
+        EntitySpawn {
+            isometry: Isometry2::new(pos, 0.),
+            velocity: Velocity2::zero(),
+            entity: Entity::Object(ObjectEntity::new(builtin_archetype("asteroid"), shape)),
+            data: (),
+            body_status: BodyStatus::Dynamic,
+            material: None,
+        },
+    );
+    uuid_to_key(uuid)
+}
+
+#[test]
+fn entities_created_and_removed_within_a_tick_are_not_reported() {
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    let mut spawns = get_initial_entities(0).into_iter();
+    let existing_id = world.gen_uuid();
+    world.spawn_entity(existing_id, spawns.next().unwrap());
+
+    world.lifecycle_updates = Some(Vec::new());
+    let transient_id = world.gen_uuid();
+    world.spawn_entity(transient_id, spawns.next().unwrap());
+    world.remove_entity(&uuid_to_key(transient_id));
+    world.remove_entity(&uuid_to_key(existing_id));
+
+    match world.lifecycle_updates.unwrap().as_slice() {
+        [WorldUpdate::EntityDeleted { id }] => assert_eq!(*id, uuid_to_key(existing_id)),
+        updates => panic!("Unexpected lifecycle updates: {:?}", updates),
+    }
+}
+
+#[test]
+fn resting_entities_are_only_updated_on_refresh() {
+    use super::entities::ObjectShape;
+
+    let snapshot_interval = CONF.network.snapshot_tick_interval;
+    let refresh_interval = CONF.network.movement_delta.refresh_interval_ticks;
+    let square = vec![
+        Point2::new(10., 10.),
+        Point2::new(-10., 10.),
+        Point2::new(-10., -10.),
+        Point2::new(10., -10.),
+    ];
+
+    // Ticks that update all entities either happen every tick or, since the caller's tick counter
+    // is one ahead of the world's, never land on a multiple of the snapshot interval of the
+    // world's own tick count
+    for &offset in &[false, true] {
+        let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+        let asteroid_id = spawn_test_object(
+            &mut world,
+            ObjectShape::Polygon(square.clone()),
+            Vector2::zeros(),
+        );
+
+        let mut refresh_ticks = Vec::new();
+        for _ in 0..(refresh_interval * 4) {
+            let tick = world.tick_count;
+            let update_all = !offset || (tick + 1) % snapshot_interval == 0;
+            let updates = world.tick(Vec::new(), update_all);
+            let moved = updates.iter().any(|update| match update {
+                WorldUpdate::Movement { id, .. } => *id == asteroid_id,
+                _ => false,
+            });
+            if moved {
+                refresh_ticks.push(tick);
+            }
+        }
+
+        assert!(refresh_ticks.len() >= 3);
+        if !offset {
+            assert_eq!(refresh_ticks[0], 0);
+        }
+        for pair in refresh_ticks.windows(2) {
+            if offset {
+                assert!(pair[1] - pair[0] >= refresh_interval);
+                assert!(pair[1] - pair[0] < refresh_interval + snapshot_interval);
+            } else {
+                assert_eq!(pair[1] - pair[0], refresh_interval);
+            }
+        }
     }
 }
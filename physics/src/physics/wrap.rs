@@ -0,0 +1,480 @@
+//! Implements the toroidal world topology that is used when `CONF.game.wrap_world` is set.  Bodies
+//! that leave one edge of the world reappear on the opposite edge.
+//!
+//! So that entities can interact across the seam, every entity near an edge gets "ghost" copies of
+//! its collider on the opposite side(s) of the world.  Each ghost is attached to a proxy body with
+//! the same mass as the original's body that is moved to follow it, offset by the size of the
+//! world, before every step.  Ghosts are kept for as long as their entity stays near the edge, so
+//! contacts with them persist between steps like any other, and whatever change in velocity a
+//! ghost picks up from contacts during a step is passed on to its original afterwards.
+//!
+//! Every contact across a seam could be found both between the first entity and a ghost of the
+//! second and between a ghost of the first and the second.  To only resolve it once, just the
+//! ghosts with positive offsets (see `is_contact_offset`) collide with things, and the rest only
+//! show up in queries like beam casts.  Ghosts never collide with each other.  All ghosts are
+//! mapped to the same entity in `handle_map` so that contacts and beam hits against them are
+//! attributed to the real entity.
+
+use std::mem;
+
+use nalgebra::{Isometry2, Point2, Translation2, Vector2};
+use ncollide2d::world::CollisionGroups;
+use nphysics2d::algebra::Velocity2;
+use nphysics2d::object::{BodyHandle, BodyStatus, ColliderHandle};
+use nphysics2d::volumetric::Volumetric;
+
+use conf::CONF;
+
+use super::world::{EntityKey, PhysicsWorldInner, COLLIDER_MARGIN};
+
+/// The collision group that the colliders of real entities belong to
+const ENTITY_GROUP: usize = 0;
+const CONTACT_GHOST_GROUP: usize = 1;
+const QUERY_GHOST_GROUP: usize = 2;
+/// Only queries belong to this group, since they're created as members of all groups
+const QUERY_GROUP: usize = 3;
+
+/// A copy of an entity's collider on the other side of a seam of the world
+#[derive(Clone, Copy, Debug)]
+pub struct Ghost {
+    /// The offset from the entity's position to the ghost's
+    pub offset: Vector2<f32>,
+    pub collider_handle: ColliderHandle,
+    /// The proxy body that the collider is attached to, or the ground for static entities
+    pub body_handle: BodyHandle,
+    /// The velocity that the proxy body was given before the last step
+    pub velocity: Velocity2<f32>,
+}
+
+/// The collision groups of the colliders of all entities
+pub fn entity_collision_groups() -> CollisionGroups {
+    CollisionGroups::new().with_membership(&[ENTITY_GROUP])
+}
+
+/// Returns whether a ghost at `offset` takes part in contacts.  Of any two opposite offsets,
+/// exactly one is a contact offset.
+fn is_contact_offset(offset: &Vector2<f32>) -> bool {
+    offset.x > 0. || (offset.x == 0. && offset.y > 0.)
+}
+
+fn ghost_collision_groups(offset: &Vector2<f32>) -> CollisionGroups {
+    if is_contact_offset(offset) {
+        CollisionGroups::new()
+            .with_membership(&[CONTACT_GHOST_GROUP])
+            .with_whitelist(&[ENTITY_GROUP])
+    } else {
+        CollisionGroups::new()
+            .with_membership(&[QUERY_GHOST_GROUP])
+            .with_whitelist(&[QUERY_GROUP])
+    }
+}
+
+pub fn world_size() -> Vector2<f32> {
+    Vector2::new(
+        CONF.game.world_max_x - CONF.game.world_min_x,
+        CONF.game.world_max_y - CONF.game.world_min_y,
+    )
+}
+
+fn wrap_coord(val: f32, min: f32, size: f32) -> f32 {
+    let offset = (val - min) % size;
+    if offset < 0. {
+        min + offset + size
+    } else {
+        min + offset
+    }
+}
+
+/// Wraps a point that has left the world bounds back around to the opposite side
+pub fn wrap_point(pt: &Point2<f32>) -> Point2<f32> {
+    let size = world_size();
+    Point2::new(
+        wrap_coord(pt.x, CONF.game.world_min_x, size.x),
+        wrap_coord(pt.y, CONF.game.world_min_y, size.y),
+    )
+}
+
+/// Returns the offsets at which copies of something located at `pt` need to exist so that
+/// everything within `margin` of it is reachable across the seams of the world.  Points that
+/// aren't near any edge have no offsets.
+pub fn ghost_offsets(pt: &Point2<f32>, margin: f32) -> Vec<Vector2<f32>> {
+    let size = world_size();
+    let axis_offsets = |val: f32, min: f32, max: f32, size: f32| -> Vec<f32> {
+        let mut offsets = vec![0.];
+        if val < min + margin {
+            offsets.push(size);
+        }
+        if val > max - margin {
+            offsets.push(-size);
+        }
+        offsets
+    };
+    let x_offsets = axis_offsets(pt.x, CONF.game.world_min_x, CONF.game.world_max_x, size.x);
+    let y_offsets = axis_offsets(pt.y, CONF.game.world_min_y, CONF.game.world_max_y, size.y);
+
+    let mut offsets = Vec::new();
+    for x_offset in &x_offsets {
+        for y_offset in &y_offsets {
+            if *x_offset != 0. || *y_offset != 0. {
+                offsets.push(Vector2::new(*x_offset, *y_offset));
+            }
+        }
+    }
+    offsets
+}
+
+impl<T> PhysicsWorldInner<T> {
+    /// Moves all bodies that have left the world bounds to the opposite side of the world
+    pub fn wrap_bodies(&mut self) {
+        for handles in self.uuid_map.values() {
+            let wrapped_pos = match self.world.rigid_body_mut(handles.body_handle) {
+                Some(body) => {
+                    let pos = *body.position();
+                    let wrapped = wrap_point(&Point2::from_coordinates(pos.translation.vector));
+                    if wrapped.coords == pos.translation.vector {
+                        continue;
+                    }
+
+                    let wrapped_pos = Isometry2::from_parts(
+                        Translation2::from_vector(wrapped.coords),
+                        pos.rotation,
+                    );
+                    body.set_position(wrapped_pos);
+                    wrapped_pos
+                }
+                None => continue,
+            };
+
+            // Colliders are only synced with their bodies during steps, so move it manually to avoid
+            // reporting stale positions until the next one
+            let pos_wrt_body = match self.world.collider(handles.collider_handle) {
+                Some(collider) => *collider.data().position_wrt_body(),
+                None => continue,
+            };
+            self.world
+                .collision_world_mut()
+                .set_position(handles.collider_handle, wrapped_pos * pos_wrt_body);
+        }
+    }
+
+    /// Removes all ghost colliders and their proxy bodies from the world
+    pub fn remove_ghosts(&mut self) {
+        for (_, ghosts) in mem::replace(&mut self.ghost_colliders, Default::default()) {
+            for ghost in ghosts {
+                self.remove_ghost(ghost);
+            }
+        }
+    }
+
+    pub(super) fn remove_ghost(&mut self, ghost: Ghost) {
+        self.handle_map.remove(&ghost.collider_handle);
+        self.world.remove_colliders(&[ghost.collider_handle]);
+        if ghost.body_handle != BodyHandle::ground() {
+            self.world.remove_bodies(&[ghost.body_handle]);
+        }
+    }
+
+    /// Creates the ghosts that entities close enough to an edge of the world to interact with
+    /// things on the other side of it need, removes the ones that are no longer needed, and moves
+    /// the rest to follow their originals.
+    pub fn update_ghosts(&mut self) {
+        let margin = CONF.game.wrap_ghost_margin;
+
+        let entity_ids: Vec<_> = self.uuid_map.keys().cloned().collect();
+        for entity_id in entity_ids {
+            let (body_handle, pos, pos_wrt_body) = {
+                let handles = &self.uuid_map[&entity_id];
+                match self.world.collider(handles.collider_handle) {
+                    Some(collider) => (
+                        handles.body_handle,
+                        *collider.position(),
+                        *collider.data().position_wrt_body(),
+                    ),
+                    None => continue,
+                }
+            };
+
+            let center = Point2::from_coordinates(pos.translation.vector);
+            let offsets = ghost_offsets(&center, margin);
+            let (mut ghosts, stale_ghosts): (Vec<Ghost>, Vec<Ghost>) = self
+                .ghost_colliders
+                .remove(&entity_id)
+                .unwrap_or_default()
+                .into_iter()
+                .partition(|ghost| offsets.contains(&ghost.offset));
+            for ghost in stale_ghosts {
+                self.remove_ghost(ghost);
+            }
+            for offset in offsets {
+                if !ghosts.iter().any(|ghost| ghost.offset == offset) {
+                    let ghost = self.create_ghost(&entity_id, offset);
+                    ghosts.push(ghost);
+                }
+            }
+
+            // Static entities are attached to the ground, which sits at the origin and never moves
+            let body_state = self
+                .world
+                .rigid_body(body_handle)
+                .map(|body| (*body.position(), *body.velocity(), body.is_active()));
+            if let Some((body_pos, velocity, is_active)) = body_state {
+                for ghost in &mut ghosts {
+                    let ghost_pos = Isometry2::new(ghost.offset, 0.) * body_pos;
+                    {
+                        let ghost_body = self.world.rigid_body_mut(ghost.body_handle).unwrap();
+                        ghost_body.set_position(ghost_pos);
+                        ghost_body.set_velocity(velocity);
+                        if is_active {
+                            ghost_body.activate();
+                        }
+                    }
+                    ghost.velocity = velocity;
+
+                    // Colliders are only synced with their bodies during steps, after collisions
+                    // have been detected
+                    self.world
+                        .collision_world_mut()
+                        .set_position(ghost.collider_handle, ghost_pos * pos_wrt_body);
+                }
+            }
+
+            if !ghosts.is_empty() {
+                self.ghost_colliders.insert(entity_id, ghosts);
+            }
+        }
+    }
+
+    fn create_ghost(&mut self, entity_id: &EntityKey, offset: Vector2<f32>) -> Ghost {
+        let (body_handle, shape, material, pos_wrt_body) = {
+            let handles = &self.uuid_map[entity_id];
+            let collider = self.world.collider(handles.collider_handle).unwrap();
+            (
+                handles.body_handle,
+                collider.shape().clone(),
+                handles.material,
+                *collider.data().position_wrt_body(),
+            )
+        };
+
+        let body_state = self
+            .world
+            .rigid_body(body_handle)
+            .map(|body| (*body.position(), body.status()));
+        let (ghost_body_handle, ghost_pos_wrt_body) = match body_state {
+            Some((body_pos, status)) => {
+                // The proxy has the same mass and local center of mass as the original so that
+                // contacts change its velocity in the same way as they would the original's
+                let ghost_body_handle = self.world.add_rigid_body(
+                    Isometry2::new(offset, 0.) * body_pos,
+                    shape.inertia(material.density),
+                    shape.center_of_mass(),
+                );
+                self.world
+                    .rigid_body_mut(ghost_body_handle)
+                    .unwrap()
+                    .set_status(status);
+                (ghost_body_handle, pos_wrt_body)
+            }
+            None => (
+                BodyHandle::ground(),
+                Isometry2::new(offset, 0.) * pos_wrt_body,
+            ),
+        };
+
+        let collider_handle = self.world.add_collider(
+            COLLIDER_MARGIN,
+            shape,
+            ghost_body_handle,
+            ghost_pos_wrt_body,
+            material.collider_material(),
+        );
+        self.world
+            .collision_world_mut()
+            .set_collision_groups(collider_handle, ghost_collision_groups(&offset));
+        self.handle_map.insert(collider_handle, entity_id.clone());
+
+        Ghost {
+            offset,
+            collider_handle,
+            body_handle: ghost_body_handle,
+            velocity: Velocity2::zero(),
+        }
+    }
+
+    /// Passes the changes in velocity that the ghosts of entities picked up from contacts during
+    /// the last step on to the entities themselves.  Ghosts have the same mass as their originals,
+    /// so this applies the impulses from contacts across the seams to the entities involved and
+    /// conserves momentum.
+    pub fn apply_ghost_impulses(&mut self) {
+        for (entity_id, ghosts) in &self.ghost_colliders {
+            let (mut linear, mut angular) = (Vector2::zeros(), 0.);
+            for ghost in ghosts {
+                match self.world.rigid_body(ghost.body_handle) {
+                    Some(body) if body.status() == BodyStatus::Dynamic && body.is_active() => {
+                        linear += body.velocity().linear - ghost.velocity.linear;
+                        angular += body.velocity().angular - ghost.velocity.angular;
+                    }
+                    _ => (),
+                }
+            }
+            if linear == Vector2::zeros() && angular == 0. {
+                continue;
+            }
+
+            let body_handle = self.uuid_map[entity_id].body_handle;
+            if let Some(body) = self.world.rigid_body_mut(body_handle) {
+                let velocity = *body.velocity();
+                body.set_velocity(Velocity2::new(
+                    velocity.linear + linear,
+                    velocity.angular + angular,
+                ));
+                body.activate();
+            }
+        }
+    }
+}
+
+#[test]
+fn points_wrap_around_the_world() {
+    let size = world_size();
+    let (min_x, min_y) = (CONF.game.world_min_x, CONF.game.world_min_y);
+
+    let inside = Point2::new(min_x + 10., min_y + 10.);
+    assert_eq!(wrap_point(&inside), inside);
+    assert_eq!(
+        wrap_point(&Point2::new(min_x - 10., min_y + 10.)),
+        Point2::new(min_x + size.x - 10., min_y + 10.)
+    );
+    assert_eq!(
+        wrap_point(&Point2::new(min_x + 10., min_y + size.y + 10.)),
+        Point2::new(min_x + 10., min_y + 10.)
+    );
+}
+
+#[test]
+fn ghosts_are_created_near_edges_and_corners() {
+    let (min_x, min_y) = (CONF.game.world_min_x, CONF.game.world_min_y);
+    let center = Point2::new(
+        (CONF.game.world_min_x + CONF.game.world_max_x) / 2.,
+        (CONF.game.world_min_y + CONF.game.world_max_y) / 2.,
+    );
+
+    assert!(ghost_offsets(&center, 50.).is_empty());
+    assert_eq!(
+        ghost_offsets(&Point2::new(min_x + 10., center.y), 50.),
+        vec![Vector2::new(world_size().x, 0.)]
+    );
+    assert_eq!(
+        ghost_offsets(&Point2::new(min_x + 10., min_y + 10.), 50.).len(),
+        3
+    );
+}
+
+/// Spawns a square asteroid for tests that moves horizontally at `velocity_x`
+#[cfg(test)]
+fn spawn_moving_square(
+    world: &mut PhysicsWorldInner,
+    pos: Vector2<f32>,
+    velocity_x: f32,
+) -> EntityKey {
+    use super::entities::ObjectShape;
+    use super::world::spawn_test_object;
+
+    let square = vec![
+        Point2::new(-5., -5.),
+        Point2::new(5., -5.),
+        Point2::new(5., 5.),
+        Point2::new(-5., 5.),
+    ];
+    let id = spawn_test_object(world, ObjectShape::Polygon(square), pos);
+    world.update_movement(
+        &id,
+        &Isometry2::new(pos, 0.),
+        &Velocity2::new(Vector2::new(velocity_x, 0.), 0.),
+        None,
+    );
+    id
+}
+
+/// Mirrors the wrapping parts of `PhysicsWorldInner::step`, which only run if the configured
+/// world wraps, and returns the number of contacts that were started during the step
+#[cfg(test)]
+fn step_wrapping_world(world: &mut PhysicsWorldInner) -> usize {
+    use ncollide2d::events::ContactEvent;
+
+    world.update_ghosts();
+    world.world.step();
+    world.apply_ghost_impulses();
+    world.wrap_bodies();
+
+    world
+        .world
+        .contact_events()
+        .into_iter()
+        .filter(|event| match event {
+            ContactEvent::Started(..) => true,
+            ContactEvent::Stopped(..) => false,
+        })
+        .count()
+}
+
+#[test]
+fn bodies_collide_across_the_seam_without_spinning() {
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    // Both squares move towards the seam from opposite sides, slightly offset from each other
+    let ids = vec![
+        spawn_moving_square(
+            &mut world,
+            Vector2::new(CONF.game.world_min_x + 8., 0.),
+            -1.,
+        ),
+        spawn_moving_square(&mut world, Vector2::new(CONF.game.world_max_x - 8., 3.), 1.),
+    ];
+
+    for _ in 0..30 {
+        step_wrapping_world(&mut world);
+    }
+
+    let velocities: Vec<Velocity2<f32>> = ids
+        .iter()
+        .map(|id| {
+            *world
+                .world
+                .rigid_body(world.uuid_map[id].body_handle)
+                .unwrap()
+                .velocity()
+        })
+        .collect();
+    // The squares stopped moving towards each other...
+    assert!(velocities[0].linear.x > -0.5);
+    assert!(velocities[1].linear.x < 0.5);
+    // ...without being flung around by contacts applied to far away points of their bodies
+    for velocity in &velocities {
+        assert!(velocity.angular.abs() < 0.1);
+        assert!(velocity.linear.norm() < 2.);
+    }
+}
+
+#[test]
+fn momentum_is_conserved_across_the_seam() {
+    let mut world: PhysicsWorldInner = PhysicsWorldInner::new();
+    // A square resting next to the seam is hit by an identical one from the other side of it
+    let resting_id =
+        spawn_moving_square(&mut world, Vector2::new(CONF.game.world_min_x + 8., 0.), 0.);
+    let moving_id =
+        spawn_moving_square(&mut world, Vector2::new(CONF.game.world_max_x - 8., 0.), 1.);
+
+    let mut started_contacts = 0;
+    for _ in 0..30 {
+        started_contacts += step_wrapping_world(&mut world);
+    }
+
+    let (resting, _) = world.entity_state(&resting_id).unwrap();
+    let (moving, _) = world.entity_state(&moving_id).unwrap();
+    // The resting square was pushed along by the collision rather than bouncing the other one off
+    assert!(resting.velocity_x > 0.25);
+    assert!((resting.velocity_x + moving.velocity_x - 1.).abs() < 0.01);
+    assert!((resting.velocity_y + moving.velocity_y).abs() < 0.01);
+    // Ghosts are kept between steps, so contacts with them aren't started over on every step
+    assert!(started_contacts < 5);
+}
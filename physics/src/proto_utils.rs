@@ -0,0 +1,366 @@
+//! Conversions between the physics world's internal types and the protocol buffer messages defined
+//! in `schema/` that are sent to and received from clients.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nalgebra::Point2;
+use protobuf::{parse_from_bytes, ProtobufError, RepeatedField};
+use uuid::Uuid;
+
+use conf::CONF;
+use physics::entities::{Entity, ObjectEntity, ObjectShape, PlayerEntity};
+use physics::updates::{MovementState, WorldUpdate};
+use physics::user_diff::{InternalUserDiff, InternalUserDiffAction};
+use physics::world::{key_to_uuid, EntityKey, PhysicsWorldInner};
+use physics::Movement;
+use protos::client_messages::{ClientMessage, ClientMessage_oneof_payload as ClientMessageContent};
+use protos::message_common::{MovementDirection, Point2 as ProtoPoint2, Uuid as ProtoUuid};
+use protos::server_messages::{
+    BeamHit as ProtoBeamHit, Capsule as ProtoCapsule, Circle as ProtoCircle, CreationEvent,
+    CreationEvent_oneof_entity as ProtoEntity, MovementUpdate, ObjectEntity as ProtoObjectEntity,
+    PlayerDeath, PlayerEntity as ProtoPlayerEntity, Polygon as ProtoPolygon, ServerMessage,
+    ServerMessage_Payload as ServerMessagePayload,
+    ServerMessage_Payload_oneof_payload as ServerMessageContent, Snapshot, Snapshot_SnapshotItem,
+    StatusUpdate, StatusUpdate_oneof_payload as StatusPayload,
+};
+
+/// The value of `StatusUpdate.other` that indicates that an entity has been deleted
+pub const DELETION_STATUS: u32 = 0;
+
+/// Converts a UUID into two unsigned 64-bit integers representing its raw byte data, matching the
+/// encoding used by the Elixir backend.
+pub fn uuid_to_proto(id: &Uuid) -> ProtoUuid {
+    let bytes = id.as_bytes();
+    let (mut data_1, mut data_2) = (0u64, 0u64);
+    for i in 0..8 {
+        data_1 |= (bytes[i] as u64) << (8 * i);
+        data_2 |= (bytes[i + 8] as u64) << (8 * i);
+    }
+
+    let mut proto_id = ProtoUuid::new();
+    proto_id.set_data_1(data_1);
+    proto_id.set_data_2(data_2);
+    proto_id
+}
+
+pub fn proto_to_uuid(proto_id: &ProtoUuid) -> Uuid {
+    let mut bytes = [0u8; 16];
+    for i in 0..8 {
+        bytes[i] = (proto_id.get_data_1() >> (8 * i)) as u8;
+        bytes[i + 8] = (proto_id.get_data_2() >> (8 * i)) as u8;
+    }
+
+    Uuid::from_bytes(&bytes).expect("Unable to build UUID from 16 bytes!")
+}
+
+impl From<MovementState> for MovementUpdate {
+    fn from(state: MovementState) -> Self {
+        let mut update = MovementUpdate::new();
+        update.set_pos_x(state.pos_x);
+        update.set_pos_y(state.pos_y);
+        update.set_rotation(state.rotation);
+        update.set_velocity_x(state.velocity_x);
+        update.set_velocity_y(state.velocity_y);
+        update.set_angular_velocity(state.angular_velocity);
+        update
+    }
+}
+
+impl From<MovementDirection> for Movement {
+    fn from(direction: MovementDirection) -> Self {
+        match direction {
+            MovementDirection::DOWN => Movement::Down,
+            MovementDirection::DOWN_LEFT => Movement::DownLeft,
+            MovementDirection::DOWN_RIGHT => Movement::DownRight,
+            MovementDirection::LEFT => Movement::Left,
+            MovementDirection::RIGHT => Movement::Right,
+            MovementDirection::STOP => Movement::Stop,
+            MovementDirection::UP => Movement::Up,
+            MovementDirection::UP_LEFT => Movement::UpLeft,
+            MovementDirection::UP_RIGHT => Movement::UpRight,
+        }
+    }
+}
+
+impl From<Movement> for MovementDirection {
+    fn from(movement: Movement) -> Self {
+        match movement {
+            Movement::Down => MovementDirection::DOWN,
+            Movement::DownLeft => MovementDirection::DOWN_LEFT,
+            Movement::DownRight => MovementDirection::DOWN_RIGHT,
+            Movement::Left => MovementDirection::LEFT,
+            Movement::Right => MovementDirection::RIGHT,
+            Movement::Stop => MovementDirection::STOP,
+            Movement::Up => MovementDirection::UP,
+            Movement::UpLeft => MovementDirection::UP_LEFT,
+            Movement::UpRight => MovementDirection::UP_RIGHT,
+        }
+    }
+}
+
+fn flatten_vertices(entity_verts: &[Point2<f32>]) -> Vec<f32> {
+    let mut vert_coords = Vec::with_capacity(entity_verts.len() * 2);
+    for vert in entity_verts {
+        vert_coords.push(vert.x);
+        vert_coords.push(vert.y);
+    }
+    vert_coords
+}
+
+pub fn entity_to_proto(entity: &Entity) -> ProtoEntity {
+    match entity {
+        Entity::Player(PlayerEntity { size, health, .. }) => {
+            let mut player = ProtoPlayerEntity::new();
+            player.set_size(*size);
+            player.set_health(*health);
+            ProtoEntity::player(player)
+        }
+        Entity::Object(ObjectEntity {
+            archetype, shape, ..
+        }) => {
+            let mut object = ProtoObjectEntity::new();
+            object.set_archetype(archetype.name.to_owned());
+            match shape {
+                ObjectShape::Polygon(vertices) => {
+                    let mut polygon = ProtoPolygon::new();
+                    polygon.set_vert_coords(flatten_vertices(vertices));
+                    object.set_polygon(polygon);
+                }
+                ObjectShape::Circle { radius } => {
+                    let mut circle = ProtoCircle::new();
+                    circle.set_radius(*radius);
+                    object.set_circle(circle);
+                }
+                ObjectShape::Capsule {
+                    half_height,
+                    radius,
+                } => {
+                    let mut capsule = ProtoCapsule::new();
+                    capsule.set_half_height(*half_height);
+                    capsule.set_radius(*radius);
+                    object.set_capsule(capsule);
+                }
+            }
+            ProtoEntity::object(object)
+        }
+    }
+}
+
+/// Builds a `CreationEvent` containing the full current state of the entity with the given ID
+pub fn creation_event<T>(
+    world: &PhysicsWorldInner<T>,
+    entity_id: &EntityKey,
+) -> Option<CreationEvent> {
+    let (movement, _center_of_mass) = world.entity_state(entity_id)?;
+    let entity = &world.uuid_map.get(entity_id)?.entity;
+
+    let mut creation_evt = CreationEvent::new();
+    creation_evt.set_movement(movement.into());
+    creation_evt.entity = Some(entity_to_proto(entity));
+    Some(creation_evt)
+}
+
+pub fn new_payload(id: &Uuid, content: ServerMessageContent) -> ServerMessagePayload {
+    let mut payload = ServerMessagePayload::new();
+    payload.set_id(uuid_to_proto(id));
+    payload.payload = Some(content);
+    payload
+}
+
+pub fn creation_payload<T>(
+    world: &PhysicsWorldInner<T>,
+    entity_id: &EntityKey,
+) -> Option<ServerMessagePayload> {
+    let creation_evt = creation_event(world, entity_id)?;
+    let mut status_update = StatusUpdate::new();
+    status_update.payload = Some(StatusPayload::creation_event(creation_evt));
+
+    Some(new_payload(
+        &key_to_uuid(entity_id),
+        ServerMessageContent::status_update(status_update),
+    ))
+}
+
+pub fn deletion_payload(entity_id: &EntityKey) -> ServerMessagePayload {
+    let mut status_update = StatusUpdate::new();
+    status_update.payload = Some(StatusPayload::other(DELETION_STATUS));
+
+    new_payload(
+        &key_to_uuid(entity_id),
+        ServerMessageContent::status_update(status_update),
+    )
+}
+
+/// Builds a snapshot containing creation events for every entity that the player with the given ID
+/// is aware of according to their interest set, other than the player themselves
+pub fn snapshot<T>(world: &PhysicsWorldInner<T>, player_id: &EntityKey) -> Snapshot {
+    let items = world
+        .interest_sets
+        .get(player_id)
+        .into_iter()
+        .flat_map(|view| view.iter())
+        .filter(|entity_id| *entity_id != player_id)
+        .filter_map(|entity_id| {
+            let creation_evt = creation_event(world, entity_id)?;
+            let mut item = Snapshot_SnapshotItem::new();
+            item.set_id(uuid_to_proto(&key_to_uuid(entity_id)));
+            item.set_item(creation_evt);
+            Some(item)
+        }).collect();
+
+    let mut snapshot = Snapshot::new();
+    snapshot.set_items(RepeatedField::from_vec(items));
+    snapshot
+}
+
+/// Converts an update produced by the physics world into a message payload.  Returns `None` for
+/// updates that aren't transmitted to clients or that refer to entities no longer in `world`.
+pub fn world_update_to_payload<T>(
+    world: &PhysicsWorldInner<T>,
+    update: WorldUpdate,
+) -> Option<ServerMessagePayload> {
+    let (id, content) = match update {
+        WorldUpdate::Movement { id, movement } => {
+            (id, ServerMessageContent::movement_update(movement.into()))
+        }
+        WorldUpdate::PlayerMovement { id, movement } => {
+            (id, ServerMessageContent::player_input(movement.into()))
+        }
+        WorldUpdate::BeamToggle { id, beam_on } => {
+            (id, ServerMessageContent::beam_toggle(beam_on))
+        }
+        WorldUpdate::BeamAim { id, aim } => {
+            let mut proto_aim = ProtoPoint2::new();
+            proto_aim.set_x(aim.x);
+            proto_aim.set_y(aim.y);
+            (id, ServerMessageContent::beam_aim(proto_aim))
+        }
+        WorldUpdate::EntityCreated { id } => return creation_payload(world, &id),
+        WorldUpdate::EntityDeleted { id } => return Some(deletion_payload(&id)),
+        WorldUpdate::HealthChange { id, health } => {
+            (id, ServerMessageContent::health_update(health))
+        }
+        WorldUpdate::PlayerDeath { id, killer_id } => {
+            let mut death = PlayerDeath::new();
+            if let Some(killer_id) = killer_id {
+                death.set_killer_id(uuid_to_proto(&key_to_uuid(&killer_id)));
+            }
+            death.set_respawn_delay_ticks(CONF.game.player_respawn_delay_ticks);
+            (id, ServerMessageContent::player_death(death))
+        }
+        WorldUpdate::BeamHit { player_id, hit } => {
+            let mut proto_hit = ProtoBeamHit::new();
+            if let Some(target_id) = hit.target_id {
+                proto_hit.set_target_id(uuid_to_proto(&key_to_uuid(&target_id)));
+            }
+            let mut point = ProtoPoint2::new();
+            point.set_x(hit.point.x);
+            point.set_y(hit.point.y);
+            proto_hit.set_point(point);
+            (player_id, ServerMessageContent::beam_hit(proto_hit))
+        }
+        WorldUpdate::Username { .. }
+        | WorldUpdate::ObjectDepleted { .. } => return None,
+    };
+
+    Some(new_payload(&key_to_uuid(&id), content))
+}
+
+/// Wraps a set of payloads into a `ServerMessage` stamped with the given tick and the current time
+pub fn new_server_message(tick: u32, payloads: Vec<ServerMessagePayload>) -> ServerMessage {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before the UNIX epoch!");
+    let nanotime = now.as_secs() * 1_000_000_000 + now.subsec_nanos() as u64;
+
+    let mut msg = ServerMessage::new();
+    msg.set_tick(tick);
+    msg.set_timestamp(nanotime);
+    msg.set_payload(RepeatedField::from_vec(payloads));
+    msg
+}
+
+/// Returns the tick that the client that sent a message was rendering, or `None` if it hadn't
+/// applied any messages from the server yet
+pub fn client_msg_render_tick(msg: &ClientMessage) -> Option<u32> {
+    match msg.get_render_tick() {
+        0 => None,
+        render_tick => Some(render_tick),
+    }
+}
+
+/// Converts a message sent by a client into an input that can be applied to the world.  Returns
+/// `None` if the message had no payload.
+pub fn client_msg_to_action(msg: ClientMessage) -> Option<InternalUserDiffAction> {
+    let action = match msg.payload? {
+        ClientMessageContent::connect(mut connect_msg) => {
+            InternalUserDiffAction::Username(connect_msg.take_username())
+        }
+        ClientMessageContent::player_move(direction) => {
+            InternalUserDiffAction::Movement(direction.into())
+        }
+        ClientMessageContent::beam_rotation(aim) => InternalUserDiffAction::BeamAim {
+            x: aim.get_x() as f32,
+            y: aim.get_y() as f32,
+        },
+        ClientMessageContent::beam_toggle(beam_on) => InternalUserDiffAction::BeamToggle(beam_on),
+    };
+
+    Some(action)
+}
+
+/// The reasons that bytes sent by a client can fail to be turned into an input
+#[derive(Debug)]
+pub enum ClientMessageError {
+    /// The bytes aren't a valid encoded `ClientMessage`
+    Malformed(ProtobufError),
+    /// The message was decoded successfully but didn't contain a payload
+    MissingPayload,
+}
+
+/// Decodes an encoded `ClientMessage` sent by the player with the given ID into an input that can
+/// be applied to the world during the next tick.
+pub fn decode_client_message(
+    player_id: EntityKey,
+    bytes: &[u8],
+) -> Result<InternalUserDiff, ClientMessageError> {
+    let msg: ClientMessage = parse_from_bytes(bytes).map_err(ClientMessageError::Malformed)?;
+    let render_tick = client_msg_render_tick(&msg);
+    let action = client_msg_to_action(msg).ok_or(ClientMessageError::MissingPayload)?;
+
+    Ok(InternalUserDiff {
+        id: player_id,
+        action,
+        render_tick,
+    })
+}
+
+#[test]
+fn client_messages_are_decoded_into_diffs() {
+    use physics::world::uuid_to_key;
+    use protobuf::Message;
+
+    let player_id = uuid_to_key(Uuid::nil());
+
+    let mut msg = ClientMessage::new();
+    msg.set_beam_toggle(true);
+    msg.set_render_tick(42);
+    let bytes = msg.write_to_bytes().unwrap();
+    match decode_client_message(player_id.clone(), &bytes) {
+        Ok(InternalUserDiff {
+            action: InternalUserDiffAction::BeamToggle(true),
+            render_tick: Some(42),
+            ..
+        }) => (),
+        _ => panic!("Expected a beam toggle diff"),
+    }
+
+    match decode_client_message(player_id.clone(), &[]) {
+        Err(ClientMessageError::MissingPayload) => (),
+        _ => panic!("Expected an empty message to have no payload"),
+    }
+    match decode_client_message(player_id, &[0xff, 0xff, 0xff]) {
+        Err(ClientMessageError::Malformed(_)) => (),
+        _ => panic!("Expected garbage bytes to be rejected"),
+    }
+}
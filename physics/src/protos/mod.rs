@@ -0,0 +1,4 @@
+pub mod channel_messages;
+pub mod client_messages;
+pub mod message_common;
+pub mod server_messages;
@@ -0,0 +1,368 @@
+//! A native game server that runs the physics world without Elixir.  It speaks the same
+//! `ClientChannelMessage`/`ServerChannelMessage` framing as the Phoenix backend over a plain
+//! WebSocket so that the `game-engine` client can connect to it directly.  This is intended for
+//! local development and integration testing.
+
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use protobuf::{parse_from_bytes, Message as ProtoMessage};
+use tungstenite::{self, server::accept, Message, WebSocket};
+use uuid::Uuid;
+
+use conf::CONF;
+use physics::updates::WorldUpdate;
+use physics::user_diff::InternalUserDiff;
+use physics::world::{key_to_uuid, uuid_to_key, PhysicsWorldInner};
+use proto_utils::{
+    client_msg_render_tick, client_msg_to_action, creation_payload, deletion_payload, new_payload,
+    new_server_message, snapshot, uuid_to_proto, world_update_to_payload,
+};
+use protos::channel_messages::{
+    ClientChannelMessage, Event, Event_oneof_payload as EventPayload, PhoenixEvent,
+    ServerChannelMessage,
+};
+use protos::server_messages::{
+    ServerMessage_Payload as ServerMessagePayload,
+    ServerMessage_Payload_oneof_payload as ServerMessageContent,
+};
+
+const TICKS_PER_SECOND: u64 = 60;
+/// How long client connection threads block waiting for incoming messages before checking for
+/// outgoing ones
+const CLIENT_POLL_INTERVAL_MS: u64 = 2;
+
+/// Events sent from client connection threads to the game loop
+enum ClientEvent {
+    Connected(Uuid, Sender<Vec<u8>>),
+    Message(Uuid, ClientChannelMessage),
+    Disconnected(Uuid),
+}
+
+struct Client {
+    outbox: Sender<Vec<u8>>,
+    /// The topic that this client joined, set once it sends a join message
+    topic: Option<String>,
+}
+
+struct GameServer {
+    world: PhysicsWorldInner,
+    clients: BTreeMap<Uuid, Client>,
+    pending_diffs: Vec<InternalUserDiff>,
+    cur_tick: u32,
+}
+
+fn custom_event(name: &str) -> Event {
+    let mut evt = Event::new();
+    evt.set_custom_event(name.into());
+    evt
+}
+
+fn phoenix_event(phoenix_event: PhoenixEvent) -> Event {
+    let mut evt = Event::new();
+    evt.set_phoenix_event(phoenix_event);
+    evt
+}
+
+impl GameServer {
+    fn new(seed: u64) -> Self {
+        let mut world = PhysicsWorldInner::new();
+        world.initialize(seed);
+        world.start_session_recording("standalone", seed);
+
+        GameServer {
+            world,
+            clients: BTreeMap::new(),
+            pending_diffs: Vec::new(),
+            cur_tick: 0,
+        }
+    }
+
+    fn encode_channel_message(
+        &self,
+        topic: &str,
+        event: Event,
+        field_ref: Option<String>,
+        payloads: Vec<ServerMessagePayload>,
+    ) -> Vec<u8> {
+        let mut msg = ServerChannelMessage::new();
+        msg.set_topic(topic.into());
+        msg.set_event(event);
+        if let Some(field_ref) = field_ref {
+            msg.set_field_ref(field_ref);
+        }
+        msg.set_payload(new_server_message(self.cur_tick, payloads));
+
+        msg.write_to_bytes()
+            .expect("Error while encoding `ServerChannelMessage` to bytes")
+    }
+
+    fn send(&self, client_id: &Uuid, bytes: Vec<u8>) {
+        if let Some(client) = self.clients.get(client_id) {
+            // If sending fails, the client has disconnected and will be cleaned up shortly
+            let _ = client.outbox.send(bytes);
+        }
+    }
+
+    /// Sends the provided payloads to a single client if it has joined a topic
+    fn send_event(
+        &self,
+        client_id: &Uuid,
+        event_name: &str,
+        payloads: Vec<ServerMessagePayload>,
+    ) {
+        if payloads.is_empty() {
+            return;
+        }
+
+        if let Some(Client {
+            topic: Some(ref topic),
+            ..
+        }) = self.clients.get(client_id)
+        {
+            let bytes =
+                self.encode_channel_message(topic, custom_event(event_name), None, payloads);
+            self.send(client_id, bytes);
+        }
+    }
+
+    /// Sends the provided payloads to all clients that have joined a topic
+    fn broadcast(&self, event_name: &str, payloads: Vec<ServerMessagePayload>) {
+        for client_id in self.clients.keys() {
+            self.send_event(client_id, event_name, payloads.clone());
+        }
+    }
+
+    fn handle_join(&mut self, client_id: Uuid, msg: ClientChannelMessage) {
+        let topic = msg.get_topic().to_owned();
+        match self.clients.get_mut(&client_id) {
+            Some(client) => client.topic = Some(topic.clone()),
+            None => return,
+        }
+
+        // Spawn the player into the world, and then reply with their ID and a snapshot of the
+        // entities around them
+        self.world.spawn_player(client_id);
+        let reply_payloads = vec![
+            new_payload(
+                &client_id,
+                ServerMessageContent::connect_successful(uuid_to_proto(&client_id)),
+            ),
+            new_payload(
+                &Uuid::nil(),
+                ServerMessageContent::snapshot(snapshot(&self.world, &uuid_to_key(client_id))),
+            ),
+        ];
+        let reply = self.encode_channel_message(
+            &topic,
+            phoenix_event(PhoenixEvent::Reply),
+            Some(msg.get_field_ref().to_owned()),
+            reply_payloads,
+        );
+        self.send(&client_id, reply);
+
+        // Let everyone know about the new player
+        if let Some(creation) = creation_payload(&self.world, &uuid_to_key(client_id)) {
+            self.broadcast(CONF.network.custom_event_name, vec![creation]);
+        }
+    }
+
+    fn handle_client_event(&mut self, evt: ClientEvent) {
+        match evt {
+            ClientEvent::Connected(client_id, outbox) => {
+                println!("Client {} connected", client_id);
+                self.clients.insert(
+                    client_id,
+                    Client {
+                        outbox,
+                        topic: None,
+                    },
+                );
+            }
+            ClientEvent::Message(client_id, mut msg) => {
+                let event_payload = match msg.get_event().payload.clone() {
+                    Some(payload) => payload,
+                    None => {
+                        println!("WARN: Received channel message without an event");
+                        return;
+                    }
+                };
+
+                match event_payload {
+                    EventPayload::phoenix_event(PhoenixEvent::Join) => {
+                        self.handle_join(client_id, msg)
+                    }
+                    EventPayload::phoenix_event(evt) => {
+                        println!("WARN: Unhandled Phoenix event from client: {:?}", evt)
+                    }
+                    EventPayload::custom_event(_) => {
+                        let client_msg = msg.take_payload();
+                        // Our ticks are the world's own, so render ticks need no conversion
+                        let render_tick = client_msg_render_tick(&client_msg);
+                        let action = match client_msg_to_action(client_msg) {
+                            Some(action) => action,
+                            None => {
+                                println!("WARN: Received `ClientMessage` without a payload");
+                                return;
+                            }
+                        };
+
+                        self.pending_diffs.push(InternalUserDiff {
+                            id: uuid_to_key(client_id),
+                            action,
+                            render_tick,
+                        });
+                    }
+                }
+            }
+            ClientEvent::Disconnected(client_id) => {
+                println!("Client {} disconnected", client_id);
+                let client = match self.clients.remove(&client_id) {
+                    Some(client) => client,
+                    None => return,
+                };
+
+                if client.topic.is_some() {
+                    let player_key = uuid_to_key(client_id);
+                    self.world.remove_entity(&player_key);
+                    self.broadcast(
+                        CONF.network.custom_event_name,
+                        vec![deletion_payload(&player_key)],
+                    );
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        let diffs = self.pending_diffs.drain(..).collect();
+        let update_all = self.cur_tick % CONF.network.snapshot_tick_interval == 0;
+        let updates: Vec<WorldUpdate> = self.world.tick(diffs, update_all);
+
+        // Each player is only sent the updates for the entities around them
+        for (player_id, player_updates) in self.world.recipient_updates(&updates) {
+            let payloads = player_updates
+                .into_iter()
+                .filter_map(|update| world_update_to_payload(&self.world, update))
+                .collect();
+            self.send_event(&key_to_uuid(&player_id), "tick", payloads);
+        }
+
+        self.cur_tick += 1;
+    }
+}
+
+fn is_timeout(err: &tungstenite::Error) -> bool {
+    match err {
+        tungstenite::Error::Io(io_err) => {
+            io_err.kind() == ErrorKind::WouldBlock || io_err.kind() == ErrorKind::TimedOut
+        }
+        _ => false,
+    }
+}
+
+/// Runs the connection for a single client, forwarding decoded messages to the game loop and
+/// writing any messages queued for it back over the WebSocket.
+fn run_client(stream: TcpStream, events: Sender<ClientEvent>) {
+    let mut socket: WebSocket<TcpStream> = match accept(stream) {
+        Ok(socket) => socket,
+        Err(err) => {
+            println!("ERROR: WebSocket handshake failed: {:?}", err);
+            return;
+        }
+    };
+    if let Err(err) = socket
+        .get_mut()
+        .set_read_timeout(Some(Duration::from_millis(CLIENT_POLL_INTERVAL_MS)))
+    {
+        println!("ERROR: Unable to set read timeout on client socket: {:?}", err);
+        return;
+    }
+
+    let client_id = Uuid::new_v4();
+    let (outbox_tx, outbox_rx): (Sender<Vec<u8>>, Receiver<Vec<u8>>) = mpsc::channel();
+    if events
+        .send(ClientEvent::Connected(client_id, outbox_tx))
+        .is_err()
+    {
+        return;
+    }
+
+    'connection: loop {
+        match socket.read_message() {
+            Ok(Message::Binary(bytes)) => match parse_from_bytes::<ClientChannelMessage>(&bytes) {
+                Ok(msg) => {
+                    if events.send(ClientEvent::Message(client_id, msg)).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => println!("ERROR: Unable to parse message from client: {:?}", err),
+            },
+            Ok(Message::Close(_)) => break,
+            Ok(_) => (),
+            Err(ref err) if is_timeout(err) => (),
+            Err(_) => break,
+        }
+
+        loop {
+            match outbox_rx.try_recv() {
+                Ok(bytes) => {
+                    if socket.write_message(Message::Binary(bytes)).is_err() {
+                        break 'connection;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => break 'connection,
+            }
+        }
+    }
+
+    let _ = events.send(ClientEvent::Disconnected(client_id));
+}
+
+/// Starts the server listening for WebSocket connections on `addr` and runs the game loop
+/// forever with a world generated from `seed`, only returning if the listener can't be created.
+pub fn run(addr: &str, seed: u64) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!(
+        "Standalone game server listening on {} with world seed {}",
+        addr, seed
+    );
+
+    let (events_tx, events_rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let events_tx = events_tx.clone();
+                    thread::spawn(move || run_client(stream, events_tx));
+                }
+                Err(err) => println!("ERROR: Failed to accept connection: {:?}", err),
+            }
+        }
+    });
+
+    let mut server = GameServer::new(seed);
+    let tick_duration = Duration::from_micros(1_000_000 / TICKS_PER_SECOND);
+    let mut next_tick = Instant::now();
+    loop {
+        while let Ok(evt) = events_rx.try_recv() {
+            server.handle_client_event(evt);
+        }
+
+        server.tick();
+
+        next_tick += tick_duration;
+        let now = Instant::now();
+        if next_tick > now {
+            thread::sleep(next_tick - now);
+        } else {
+            // We've fallen behind; don't try to catch up by running ticks back-to-back
+            next_tick = now;
+        }
+    }
+}
@@ -2,55 +2,179 @@
 
 use std::f32::consts::PI;
 
-use nalgebra::{Isometry2, Point2, Vector2};
+use nalgebra::{distance, Isometry2, Point2, Vector2};
 use nphysics2d::algebra::Velocity2;
-use nphysics2d::object::BodyStatus;
-use rand::{thread_rng, Rng};
+use rand::prng::ChaChaRng;
+use rand::{Rng, SeedableRng};
+use uuid::Uuid;
 
-// use conf::CONF;
-use physics::entities::{AsteroidEntity, BarrierEntity, Entity, EntitySpawn};
+use conf::{AsteroidSizeClassConf, ForceFieldGenConf, CONF};
+use physics::entities::{builtin_archetype, Entity, EntitySpawn, ObjectEntity, ObjectShape};
+use physics::force_fields::{ForceField, ForceFieldKind};
+
+/// The RNG used for all randomness in the world.  It is seeded explicitly so that worlds can be
+/// reproduced exactly.
+pub type WorldRng = ChaChaRng;
+
+pub fn rng_from_seed(seed: u64) -> WorldRng {
+    let mut seed_bytes = [0u8; 32];
+    for i in 0..8 {
+        seed_bytes[i] = (seed >> (8 * i)) as u8;
+    }
+
+    ChaChaRng::from_seed(seed_bytes)
+}
+
+/// Creates the RNG that a world uses at runtime, such as for generating entity IDs.  It is a
+/// separate stream from the ones used to generate the world's initial entities and force fields so
+/// that IDs aren't correlated with the world's layout.
+pub fn world_rng(seed: u64) -> WorldRng {
+    let mut rng = rng_from_seed(seed);
+    rng.set_stream(2);
+    rng
+}
+
+/// Recreates a world RNG created from `seed` that has had `word_pos` 32-bit words drawn from it
+pub fn restore_rng(seed: u64, word_pos: u64) -> WorldRng {
+    let mut rng = world_rng(seed);
+    rng.set_word_pos(word_pos as u128);
+    rng
+}
+
+/// Generates a v4 UUID using randomness from the provided RNG rather than the OS
+pub fn gen_uuid<R: Rng>(rng: &mut R) -> Uuid {
+    let mut bytes = [0u8; 16];
+    rng.fill(&mut bytes);
+    Uuid::from_random_bytes(bytes)
+}
 
 #[inline(always)]
 fn pt2(x: f32, y: f32) -> Point2<f32> {
     Point2::new(x, y)
 }
 
-fn get_asteroid_vertices() -> Vec<Point2<f32>> {
-    [
-        pt2(-1., 1.),
-        pt2(-1., -1.),
-        pt2(1., -1.),
-        pt2(2., 0.),
-        pt2(1., 1.),
-    ]
-        .into_iter()
-        .map(|pt| pt * 20.)
-        .collect()
+/// Generates a random asteroid outline centered around the origin.  Vertices are placed in order
+/// at jittered angles around a circle with jittered radii, so the outline is always a simple
+/// polygon but is often concave.
+fn create_asteroid_vertices<R: Rng>(
+    rng: &mut R,
+    radius: f32,
+    vertex_count: usize,
+) -> Vec<Point2<f32>> {
+    let angle_step = 2. * PI / vertex_count as f32;
+    (0..vertex_count)
+        .map(|i| {
+            let angle = (i as f32 + rng.gen_range(-0.3, 0.3)) * angle_step;
+            let vert_radius = radius * rng.gen_range(0.7, 1.0);
+            pt2(angle.cos() * vert_radius, angle.sin() * vert_radius)
+        }).collect()
+}
+
+fn pick_size_class<R: Rng>(rng: &mut R) -> &'static AsteroidSizeClassConf {
+    let size_classes = &CONF.game.asteroid_field.size_classes;
+    let total_weight: f32 = size_classes.iter().map(|class| class.weight).sum();
+    let mut choice = rng.gen_range(0., total_weight);
+
+    for class in size_classes {
+        if choice < class.weight {
+            return class;
+        }
+        choice -= class.weight;
+    }
+
+    &size_classes[size_classes.len() - 1]
 }
 
-fn create_asteroid() -> EntitySpawn {
-    let mut rng = thread_rng();
+fn create_asteroid<R: Rng>(
+    rng: &mut R,
+    pos: Point2<f32>,
+    radius: f32,
+    size_class: &AsteroidSizeClassConf,
+) -> EntitySpawn {
+    let vertex_count = rng.gen_range(size_class.min_vertices, size_class.max_vertices + 1);
+    let heading = rng.gen_range(0., 2.0 * PI);
+    let speed = rng.gen_range(0., size_class.max_velocity);
 
+    let archetype = builtin_archetype("asteroid");
     EntitySpawn {
-        isometry: Isometry2::new(
-            Vector2::new(
-                // rng.gen_range(CONF.game.world_min_x, CONF.game.world_max_x),
-                // rng.gen_range(CONF.game.world_min_y, CONF.game.world_max_y),
-                rng.gen_range(50., 500.),
-                rng.gen_range(50., 500.),
-            ),
-            rng.gen_range(0., 2.0 * PI),
-        ),
-        entity: Entity::Asteroid(AsteroidEntity {
-            vertices: get_asteroid_vertices(),
-        }),
+        isometry: Isometry2::new(pos.coords, rng.gen_range(0., 2.0 * PI)),
+        entity: Entity::Object(ObjectEntity::new(
+            archetype,
+            ObjectShape::Polygon(create_asteroid_vertices(rng, radius, vertex_count)),
+        )),
         velocity: Velocity2::new(
-            Vector2::new(rng.gen_range(0.0, 0.05), rng.gen_range(0.0, 0.05)),
-            rng.gen_range(-0.025, 0.025),
+            Vector2::new(heading.cos() * speed, heading.sin() * speed),
+            rng.gen_range(
+                -size_class.max_angular_velocity,
+                size_class.max_angular_velocity,
+            ),
         ),
         data: (),
-        body_status: BodyStatus::Dynamic,
+        body_status: archetype.body_status(),
+        material: None,
+    }
+}
+
+/// Scatters asteroids over the entire world using dart throwing on a uniform grid so that no
+/// two asteroids are closer than the configured minimum spacing.  Asteroids that can't be placed
+/// after the configured number of attempts are skipped, so crowded configurations produce fewer
+/// asteroids than requested rather than overlapping ones.
+pub fn create_asteroid_field<R: Rng>(rng: &mut R) -> Vec<EntitySpawn> {
+    let field_conf = &CONF.game.asteroid_field;
+    let max_radius = field_conf
+        .size_classes
+        .iter()
+        .fold(0.0f32, |acc, class| acc.max(class.max_radius));
+    // Any two asteroids that are too close together are guaranteed to be in adjacent cells
+    let cell_size = 2. * max_radius + field_conf.min_spacing;
+    let (min_x, max_x) = (CONF.game.world_min_x, CONF.game.world_max_x);
+    let (min_y, max_y) = (CONF.game.world_min_y, CONF.game.world_max_y);
+    let grid_width = ((max_x - min_x) / cell_size).ceil() as usize + 1;
+    let grid_height = ((max_y - min_y) / cell_size).ceil() as usize + 1;
+    let cell_of = |pos: &Point2<f32>| -> (usize, usize) {
+        (
+            ((pos.x - min_x) / cell_size) as usize,
+            ((pos.y - min_y) / cell_size) as usize,
+        )
+    };
+
+    // Each cell holds the `(position, radius)` of all asteroids placed within it
+    let mut grid: Vec<Vec<(Point2<f32>, f32)>> = vec![Vec::new(); grid_width * grid_height];
+    let mut asteroids = Vec::with_capacity(field_conf.count);
+
+    for _ in 0..field_conf.count {
+        let size_class = pick_size_class(rng);
+        let radius = rng.gen_range(size_class.min_radius, size_class.max_radius);
+
+        for _ in 0..field_conf.max_placement_attempts {
+            let pos = pt2(
+                rng.gen_range(min_x + radius, max_x - radius),
+                rng.gen_range(min_y + radius, max_y - radius),
+            );
+            let (cell_x, cell_y) = cell_of(&pos);
+
+            let mut is_free = true;
+            'neighbors: for neighbor_x in cell_x.saturating_sub(1)..(cell_x + 2).min(grid_width) {
+                for neighbor_y in cell_y.saturating_sub(1)..(cell_y + 2).min(grid_height) {
+                    for (other_pos, other_radius) in &grid[neighbor_y * grid_width + neighbor_x] {
+                        if distance(&pos, other_pos) < radius + other_radius + field_conf.min_spacing
+                        {
+                            is_free = false;
+                            break 'neighbors;
+                        }
+                    }
+                }
+            }
+
+            if is_free {
+                grid[cell_y * grid_width + cell_x].push((pos, radius));
+                asteroids.push(create_asteroid(rng, pos, radius, size_class));
+                break;
+            }
+        }
     }
+
+    asteroids
 }
 
 fn create_barrier(width: f32, height: f32, isometry: Isometry2<f32>) -> EntitySpawn {
@@ -63,30 +187,166 @@ fn create_barrier(width: f32, height: f32, isometry: Isometry2<f32>) -> EntitySp
         pt2(half_width, -half_height),
     ];
 
+    let archetype = builtin_archetype("barrier");
     EntitySpawn {
         isometry,
-        entity: Entity::Barrier(BarrierEntity { vertices }),
+        entity: Entity::Object(ObjectEntity::new(archetype, ObjectShape::Polygon(vertices))),
         velocity: Velocity2::zero(),
         data: (),
-        body_status: BodyStatus::Static,
+        body_status: archetype.body_status(),
+        material: None,
     }
 }
 
-pub fn get_initial_entities() -> Vec<EntitySpawn> {
-    vec![
-        create_asteroid(),
-        create_asteroid(),
-        create_barrier(500.0, 100.0, Isometry2::new(Vector2::new(300.0, 0.0), 0.0)),
+/// Creates static barriers just outside of each edge of the configured world bounds so that
+/// nothing can leave the playable area.  The barriers overlap at the corners.
+pub fn create_world_boundaries() -> Vec<EntitySpawn> {
+    let (min_x, max_x) = (CONF.game.world_min_x, CONF.game.world_max_x);
+    let (min_y, max_y) = (CONF.game.world_min_y, CONF.game.world_max_y);
+    let thickness = CONF.game.world_boundary_thickness;
+    let (center_x, center_y) = ((min_x + max_x) / 2., (min_y + max_y) / 2.);
+    let outer_width = max_x - min_x + 2. * thickness;
+    let outer_height = max_y - min_y + 2. * thickness;
+
+    let horizontal = |y: f32| {
         create_barrier(
-            500.0,
-            100.0,
-            Isometry2::new(Vector2::new(300.0, 600.0), 0.0),
-        ),
-        create_barrier(100.0, 500.0, Isometry2::new(Vector2::new(0.0, 300.0), 0.0)),
+            outer_width,
+            thickness,
+            Isometry2::new(Vector2::new(center_x, y), 0.0),
+        )
+    };
+    let vertical = |x: f32| {
         create_barrier(
-            100.0,
-            500.0,
-            Isometry2::new(Vector2::new(600.0, 300.0), 0.0),
-        ),
+            thickness,
+            outer_height,
+            Isometry2::new(Vector2::new(x, center_y), 0.0),
+        )
+    };
+
+    vec![
+        horizontal(min_y - thickness / 2.),
+        horizontal(max_y + thickness / 2.),
+        vertical(min_x - thickness / 2.),
+        vertical(max_x + thickness / 2.),
     ]
 }
+
+/// Places `conf.count` force fields at random points in the world, building each one's kind from
+/// its randomly chosen strength with `make_kind`
+fn create_force_fields<R: Rng, F: Fn(&mut R, f32) -> ForceFieldKind>(
+    rng: &mut R,
+    conf: &ForceFieldGenConf,
+    make_kind: F,
+) -> Vec<ForceField> {
+    (0..conf.count)
+        .map(|_| {
+            let center = pt2(
+                rng.gen_range(CONF.game.world_min_x, CONF.game.world_max_x),
+                rng.gen_range(CONF.game.world_min_y, CONF.game.world_max_y),
+            );
+            let radius = rng.gen_range(conf.min_radius, conf.max_radius);
+            let strength = rng.gen_range(conf.min_strength, conf.max_strength);
+
+            ForceField {
+                center,
+                radius,
+                kind: make_kind(rng, strength),
+            }
+        }).collect()
+}
+
+/// Generates the force fields for a new world.  They are drawn from a separate stream of the
+/// world's RNG so that they don't change the layout of its entities.
+pub fn get_initial_force_fields(seed: u64) -> Vec<ForceField> {
+    let mut rng = rng_from_seed(seed);
+    rng.set_stream(1);
+    let conf = &CONF.game.force_fields;
+
+    let mut fields = create_force_fields(&mut rng, &conf.gravity_wells, |_, strength| {
+        ForceFieldKind::GravityWell { strength }
+    });
+    fields.extend(create_force_fields(&mut rng, &conf.repulsors, |_, strength| {
+        ForceFieldKind::Repulsor { strength }
+    }));
+    fields.extend(create_force_fields(&mut rng, &conf.currents, |rng, strength| {
+        let heading = rng.gen_range(0., 2.0 * PI);
+        ForceFieldKind::Current {
+            acceleration: Vector2::new(heading.cos(), heading.sin()) * strength,
+        }
+    }));
+    fields
+}
+
+/// Generates the set of entities that the world starts out with.  All randomness is derived from
+/// `seed`, so the same seed always produces the same entities.
+pub fn get_initial_entities(seed: u64) -> Vec<EntitySpawn> {
+    let mut rng = rng_from_seed(seed);
+
+    let mut entities = create_asteroid_field(&mut rng);
+    // Wrapping worlds have no edges for entities to escape through
+    if !CONF.game.wrap_world {
+        entities.extend(create_world_boundaries());
+    }
+    entities
+}
+
+#[test]
+fn asteroid_field_is_in_bounds_and_non_overlapping() {
+    let asteroids = create_asteroid_field(&mut rng_from_seed(1337));
+    assert!(!asteroids.is_empty());
+
+    let bounds: Vec<(Point2<f32>, f32)> = asteroids
+        .iter()
+        .map(|spawn| {
+            let vertices = match spawn.entity {
+                Entity::Object(ObjectEntity {
+                    archetype,
+                    shape: ObjectShape::Polygon(ref vertices),
+                    ..
+                }) if archetype.name == "asteroid" => vertices,
+                _ => panic!("Asteroid field contained a non-asteroid entity"),
+            };
+            let radius = vertices
+                .iter()
+                .fold(0.0f32, |acc, vert| acc.max(vert.coords.norm()));
+            (Point2::from_coordinates(spawn.isometry.translation.vector), radius)
+        }).collect();
+
+    for (i, (pos, radius)) in bounds.iter().enumerate() {
+        assert!(pos.x - radius >= CONF.game.world_min_x);
+        assert!(pos.x + radius <= CONF.game.world_max_x);
+        assert!(pos.y - radius >= CONF.game.world_min_y);
+        assert!(pos.y + radius <= CONF.game.world_max_y);
+
+        for (other_pos, other_radius) in &bounds[i + 1..] {
+            assert!(distance(pos, other_pos) >= radius + other_radius);
+        }
+    }
+}
+
+#[test]
+fn world_boundaries_enclose_the_world_bounds() {
+    let boundaries = create_world_boundaries();
+    assert_eq!(boundaries.len(), 4);
+
+    for spawn in &boundaries {
+        let vertices = match spawn.entity {
+            Entity::Object(ObjectEntity {
+                archetype,
+                shape: ObjectShape::Polygon(ref vertices),
+                ..
+            }) if archetype.name == "barrier" => vertices,
+            _ => panic!("World boundary wasn't a barrier"),
+        };
+
+        // Each barrier lies entirely on the outside of one of the edges
+        let world_verts: Vec<Point2<f32>> =
+            vertices.iter().map(|vert| spawn.isometry * vert).collect();
+        assert!(
+            world_verts.iter().all(|vert| vert.x <= CONF.game.world_min_x)
+                || world_verts.iter().all(|vert| vert.x >= CONF.game.world_max_x)
+                || world_verts.iter().all(|vert| vert.y <= CONF.game.world_min_y)
+                || world_verts.iter().all(|vert| vert.y >= CONF.game.world_max_y)
+        );
+    }
+}